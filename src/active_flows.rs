@@ -0,0 +1,54 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Flows that were started but not yet completed in the browser. Flows the
+/// user never completes are forgotten after a while, so they don't hold up
+/// new flows forever.
+#[derive(Debug, Default)]
+pub struct ActiveFlows {
+    flows: Mutex<HashMap<String, Instant>>,
+}
+
+impl ActiveFlows {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Instant>> {
+        let mut flows = self.flows.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        flows.retain(|_, expires| *expires > now);
+        flows
+    }
+
+    /// Track new flows, unless that would make more than `max` flows
+    /// active. Either all flows start or none. Returns whether they may
+    /// start.
+    pub fn start(&self, new: &[String], max: Option<usize>, ttl: Duration) -> bool {
+        let mut flows = self.lock();
+        if max.map_or(false, |max| flows.len() + new.len() > max) {
+            return false;
+        }
+        let expires = Instant::now() + ttl;
+        for flow in new {
+            flows.insert(flow.clone(), expires);
+        }
+        true
+    }
+
+    /// Stop tracking a flow, as it was completed in the browser.
+    pub fn complete(&self, flow: &str) {
+        self.lock().remove(flow);
+    }
+
+    pub fn count(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Stop tracking all flows, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut flows = self.lock();
+        let count = flows.len();
+        flows.clear();
+        count
+    }
+}
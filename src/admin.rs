@@ -0,0 +1,276 @@
+use rocket::{
+    delete,
+    figment::Figment,
+    get,
+    http::Status,
+    post,
+    request::{self, FromRequest, Request},
+    serde::json::Json,
+    State,
+};
+use tracing::info;
+use verder_helpen_proto::AuthResult;
+
+use crate::{
+    active_flows::ActiveFlows,
+    attr_sink::AttrSink,
+    config::{self, Config, ConfigSnapshot, SharedConfig},
+    idempotency::IdempotencyStore,
+    nonce::NonceStore,
+    payload::PayloadStore,
+    session::{SessionState, SessionStatus, SessionStore},
+    step_up::StepUpStore,
+    token, Error,
+};
+
+/// The figment the configuration was loaded from at launch, which reloads
+/// read it from again.
+pub struct ConfigSource(pub Figment);
+
+/// Guard for the admin endpoints. These require the configured admin token as
+/// a bearer token, and don't exist when no admin token is configured.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let config = match request.rocket().state::<SharedConfig>() {
+            Some(config) => config.snapshot(),
+            None => return request::Outcome::Error((Status::InternalServerError, ())),
+        };
+        let expected = match config.admin_token() {
+            Some(token) => token,
+            None => return request::Outcome::Error((Status::NotFound, ())),
+        };
+
+        let provided = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+        if provided == Some(expected) {
+            request::Outcome::Success(Admin)
+        } else {
+            request::Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/// Re-read the configuration and make it active for all new requests. Returns
+/// the settings that changed, with their old and new values. Settings that
+/// only act at launch, like CORS and session expiry, are not affected.
+#[post("/admin/reload")]
+pub async fn reload(
+    _admin: Admin,
+    config: &State<SharedConfig>,
+    source: &State<ConfigSource>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let new_config = source
+        .0
+        .extract::<Config>()
+        .map_err(|e| config::Error::Figment(Box::new(e)))?;
+
+    let new_summary = new_config.summary();
+    let old_summary = config.replace(new_config).summary();
+
+    let mut changes = serde_json::Map::new();
+    for (key, new_value) in new_summary {
+        let old_value = old_summary
+            .get(&key)
+            .cloned()
+            .unwrap_or(serde_json::Value::Null);
+        if old_value != new_value {
+            changes.insert(
+                key,
+                serde_json::json!({ "old": old_value, "new": new_value }),
+            );
+        }
+    }
+
+    info!(changed = changes.len(), "Reloaded configuration");
+    Ok(Json(serde_json::Value::Object(changes)))
+}
+
+/// Show the effective configuration, with secrets redacted.
+#[get("/admin/config")]
+pub async fn show_config(_admin: Admin, config: ConfigSnapshot) -> Json<serde_json::Value> {
+    Json(config.redacted())
+}
+
+/// List the tracked sessions, optionally only the active or expired ones.
+/// Finished sessions are not kept, so are never listed.
+#[get("/admin/sessions?<state>")]
+pub async fn list_sessions(
+    _admin: Admin,
+    sessions: &State<SessionStore>,
+    state: Option<SessionState>,
+) -> Json<Vec<SessionStatus>> {
+    Json(sessions.list(state))
+}
+
+/// Number of flows started but not yet completed in the browser, with the
+/// configured maximum.
+#[get("/admin/flows")]
+pub async fn active_flows(
+    _admin: Admin,
+    config: ConfigSnapshot,
+    flows: &State<ActiveFlows>,
+) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "active": flows.count(),
+        "max": config.max_active_flows(),
+    }))
+}
+
+/// The in-memory state of the provider, as cleared by a reset.
+pub struct ResettableState<'r> {
+    sessions: &'r SessionStore,
+    nonces: &'r NonceStore,
+    idempotency: &'r IdempotencyStore,
+    payloads: &'r PayloadStore,
+    step_ups: &'r StepUpStore,
+    active_flows: &'r ActiveFlows,
+    attr_sink: &'r AttrSink,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ResettableState<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let rocket = request.rocket();
+        match (
+            rocket.state::<SessionStore>(),
+            rocket.state::<NonceStore>(),
+            rocket.state::<IdempotencyStore>(),
+            rocket.state::<PayloadStore>(),
+            rocket.state::<StepUpStore>(),
+            rocket.state::<ActiveFlows>(),
+            rocket.state::<AttrSink>(),
+        ) {
+            (
+                Some(sessions),
+                Some(nonces),
+                Some(idempotency),
+                Some(payloads),
+                Some(step_ups),
+                Some(active_flows),
+                Some(attr_sink),
+            ) => request::Outcome::Success(ResettableState {
+                sessions,
+                nonces,
+                idempotency,
+                payloads,
+                step_ups,
+                active_flows,
+                attr_sink,
+            }),
+            _ => request::Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+/// Clear all in-memory state, so a test suite sharing the provider starts
+/// from a clean slate. Returns how many entries were dropped from each
+/// store, which are all zero when the stateful features are not used.
+#[post("/admin/reset")]
+pub async fn reset(_admin: Admin, state: ResettableState<'_>) -> Json<serde_json::Value> {
+    let cleared = serde_json::json!({
+        "sessions": state.sessions.clear(),
+        "nonces": state.nonces.clear(),
+        "idempotency_keys": state.idempotency.clear(),
+        "payloads": state.payloads.clear(),
+        "step_ups": state.step_ups.clear(),
+        "active_flows": state.active_flows.clear(),
+        "attr_sink": state.attr_sink.clear(),
+    });
+    info!(%cleared, "Reset in-memory state");
+    Json(cleared)
+}
+
+/// Make the attr sink answer results with the given status, and the request
+/// body as its body, to simulate a failing relying party. See `AttrSink`.
+/// Only error statuses are accepted, anything else would not be a failure.
+#[post("/admin/attr_sink/response?<status>", data = "<body>")]
+pub async fn set_attr_sink_response(
+    _admin: Admin,
+    sink: &State<AttrSink>,
+    status: u16,
+    body: String,
+) -> Status {
+    match Status::from_code(status) {
+        Some(status) if status.code >= 400 => {
+            info!(status = status.code, "Attr sink answers with a failure");
+            sink.respond_with(status, body);
+            Status::NoContent
+        }
+        _ => Status::BadRequest,
+    }
+}
+
+/// Make the attr sink answer results with 200 again.
+#[delete("/admin/attr_sink/response")]
+pub async fn clear_attr_sink_response(_admin: Admin, sink: &State<AttrSink>) -> Status {
+    if sink.respond_ok() {
+        info!("Attr sink answers with 200 again");
+    }
+    Status::NoContent
+}
+
+/// Make another of the rotated signing keys the active one, returning the
+/// kid it replaces. Reloading the configuration activates the configured kid
+/// again.
+#[post("/admin/signing_key/<kid>/activate")]
+pub async fn activate_signing_key(
+    _admin: Admin,
+    config: ConfigSnapshot,
+    kid: String,
+) -> Result<Option<Json<serde_json::Value>>, Error> {
+    let rotation = match config.key_rotation() {
+        Some(rotation) => rotation,
+        None => return Ok(None),
+    };
+    let previous = rotation.activate(&kid)?;
+    info!(
+        kid = kid.as_str(),
+        previous = previous.as_str(),
+        "Switched signing key"
+    );
+    Ok(Some(Json(serde_json::json!({
+        "active": kid,
+        "previous": previous,
+    }))))
+}
+
+/// Expire a session without waiting for its ttl, returning its status from
+/// before. Later updates of the session report it as expired.
+#[post("/admin/session/<id>/expire")]
+pub async fn expire_session(
+    _admin: Admin,
+    sessions: &State<SessionStore>,
+    id: String,
+) -> Option<Json<SessionStatus>> {
+    let prior = sessions.expire(&id)?;
+    info!(
+        session = id.as_str(),
+        flow_id = id.as_str(),
+        "Expired session"
+    );
+    Some(Json(prior))
+}
+
+/// Show the contents of a result token. Only available when the keys to
+/// decrypt and verify results are configured, or when tokens are
+/// deterministic.
+#[post("/admin/inspect", data = "<token>")]
+pub async fn inspect(
+    _admin: Admin,
+    config: ConfigSnapshot,
+    token: String,
+) -> Result<Option<Json<AuthResult>>, Error> {
+    match token::inspect(&config, &token) {
+        Some(result) => Ok(Some(Json(result?))),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::{Mutex, PoisonError};
+
+use rocket::{
+    get,
+    http::Status,
+    post, put,
+    request::{self, FromRequest, Request},
+    response::status::Custom,
+    State,
+};
+use tracing::info;
+
+use crate::config::SharedConfig;
+
+/// Guard for the test endpoints, which don't exist unless enabled in the
+/// configuration.
+pub struct TestEndpoints;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TestEndpoints {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        match request.rocket().state::<SharedConfig>() {
+            Some(config) if config.snapshot().test_endpoints() => {
+                request::Outcome::Success(TestEndpoints)
+            }
+            Some(_) => request::Outcome::Error((Status::NotFound, ())),
+            None => request::Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+/// The last result posted to the sink, so a flow can use the provider
+/// itself as its attr_url.
+///
+/// The sink can also play a failing relying party. Point the attr_url of a
+/// flow at `<internal_url>/test/attr_sink`, and set the response with
+/// `POST /admin/attr_sink/response?status=<code>`, with the body of the
+/// response as the request body. The sink then answers every result with
+/// that response, until `DELETE /admin/attr_sink/response` or a reset.
+#[derive(Debug, Default)]
+pub struct AttrSink {
+    last: Mutex<Option<String>>,
+    response: Mutex<Option<(Status, String)>>,
+}
+
+impl AttrSink {
+    /// Answer results with `status` and `body`, instead of 200.
+    pub fn respond_with(&self, status: Status, body: String) {
+        *self.response.lock().unwrap_or_else(PoisonError::into_inner) = Some((status, body));
+    }
+
+    /// Answer results with 200 again, returning whether another response
+    /// was set.
+    pub fn respond_ok(&self) -> bool {
+        self.response
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .is_some()
+    }
+
+    /// Forget the last result and answer with 200 again, returning how many
+    /// results were dropped.
+    pub fn clear(&self) -> usize {
+        self.respond_ok();
+        let mut last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+        usize::from(last.take().is_some())
+    }
+}
+
+/// Receive a result as an attr_url would, keeping only the most recent one.
+/// Results are kept even when answered with a failure.
+#[post("/test/attr_sink", data = "<token>")]
+pub async fn receive(
+    _test: TestEndpoints,
+    sink: &State<AttrSink>,
+    token: String,
+) -> Custom<String> {
+    record(sink, token)
+}
+
+/// Receive a result delivered with `attr_url_method = "PUT"`, like `receive`.
+#[put("/test/attr_sink", data = "<token>")]
+pub async fn receive_put(
+    _test: TestEndpoints,
+    sink: &State<AttrSink>,
+    token: String,
+) -> Custom<String> {
+    record(sink, token)
+}
+
+/// Keep a received result, and answer it with the configured response.
+fn record(sink: &AttrSink, token: String) -> Custom<String> {
+    *sink.last.lock().unwrap_or_else(PoisonError::into_inner) = Some(token);
+    match sink
+        .response
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+    {
+        Some((status, body)) => {
+            info!(
+                status = status.code,
+                "Attr sink received a result, answering with a failure"
+            );
+            Custom(status, body)
+        }
+        None => {
+            info!("Attr sink received a result");
+            Custom(Status::Ok, String::new())
+        }
+    }
+}
+
+/// The most recent result received by the sink, as it was posted.
+#[get("/test/attr_sink/last")]
+pub async fn last(_test: TestEndpoints, sink: &State<AttrSink>) -> Option<String> {
+    sink.last
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
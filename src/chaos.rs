@@ -0,0 +1,77 @@
+use std::{
+    f64::consts::PI,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::config::DelayDistribution;
+
+/// Source of randomness for simulating an unreliable identity provider.
+/// With a seed, every run of the provider makes the same decisions in the
+/// same order.
+pub struct Chaos {
+    rng: Mutex<StdRng>,
+}
+
+impl Chaos {
+    pub fn new(seed: Option<u64>) -> Chaos {
+        Chaos {
+            rng: Mutex::new(match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            }),
+        }
+    }
+
+    fn rng(&self) -> MutexGuard<'_, StdRng> {
+        self.rng.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether to fail a flow, for a fraction `rate` of them. The rate is
+    /// checked to be between 0 and 1 when loading the configuration.
+    pub fn fail(&self, rate: f64) -> bool {
+        rate > 0.0 && self.rng().gen_bool(rate)
+    }
+
+    /// Sample the response delay of a flow, in milliseconds. The fixed
+    /// distribution always gives `fixed_ms`.
+    pub fn delay_ms(&self, distribution: DelayDistribution, fixed_ms: u64) -> u64 {
+        match distribution {
+            DelayDistribution::Fixed => fixed_ms,
+            DelayDistribution::Uniform { min_ms, max_ms } => self.rng().gen_range(min_ms..=max_ms),
+            DelayDistribution::Normal { mean_ms, stddev_ms } => {
+                // Box-Muller transform, rand itself has no normal distribution
+                let mut rng = self.rng();
+                let u1 = 1.0 - rng.gen::<f64>();
+                let u2 = rng.gen::<f64>();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+                (mean_ms + stddev_ms * z).max(0.0).round() as u64
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failures(chaos: &Chaos, rate: f64) -> Vec<bool> {
+        (0..32).map(|_| chaos.fail(rate)).collect()
+    }
+
+    #[test]
+    fn repeats_decisions_for_a_seed() {
+        let decisions = failures(&Chaos::new(Some(7)), 0.5);
+        assert_eq!(failures(&Chaos::new(Some(7)), 0.5), decisions);
+        assert!(decisions.contains(&true) && decisions.contains(&false));
+        assert_ne!(failures(&Chaos::new(Some(8)), 0.5), decisions);
+    }
+
+    #[test]
+    fn fails_never_or_always_at_the_extremes() {
+        let chaos = Chaos::new(None);
+        assert!(failures(&chaos, 0.0).iter().all(|fail| !fail));
+        assert!(failures(&chaos, 1.0).iter().all(|fail| *fail));
+    }
+}
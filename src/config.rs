@@ -1,17 +1,163 @@
-use std::{collections::HashMap, convert::TryFrom, error::Error as StdError, fmt::Display};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::TryFrom,
+    error::Error as StdError,
+    fmt::Display,
+    num::{NonZeroU32, NonZeroUsize},
+    ops::Deref,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
 
-use josekit::{jwe::JweEncrypter, jws::JwsSigner};
-use serde::Deserialize;
+use arc_swap::ArcSwap;
+use josekit::{
+    jwe::{JweDecrypter, JweEncrypter},
+    jws::{JwsSigner, JwsVerifier},
+    JoseError,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, USER_AGENT},
+    Method,
+};
+use rocket::{
+    figment::{
+        providers::{Format, Toml},
+        Figment,
+    },
+    form::FromFormField,
+    http::Status,
+    request::{self, FromRequest, Request},
+    response::Redirect,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
 use verder_helpen_jwt::{EncryptionKeyConfig, SignKeyConfig};
 
+use crate::{
+    cors::CorsConfig,
+    jwks,
+    keys::{self, ContentEncoding, EncryptionAlgorithm, SigningAlgorithm},
+    locale::{self, Locales, Messages},
+    request_log::RequestLogLevel,
+    security_headers::SecurityHeadersConfig,
+    session,
+};
+
 #[derive(Debug)]
 pub enum Error {
     UnknownAttribute(String),
+    UnknownAttributes(Vec<String>),
+    NoAttributes,
+    DisallowedHost(String),
+    ContinuationOutsideBase(String),
+    InsecureUrl(&'static str, String),
+    Url(url::ParseError),
+    MappingFile(String, std::io::Error),
+    LocaleFile(String, std::io::Error),
+    UnknownLocale(String),
+    Figment(Box<rocket::figment::Error>),
     Yaml(serde_yaml::Error),
     Json(serde_json::Error),
+    UnsupportedAlgorithm(&'static str, &'static str),
+    MissingKey(&'static str),
+    IncompleteTls,
+    LoggingResultsWithHttps,
+    JsonResultsWithHttps,
+    RequestedAttributesWithHttps,
+    JsonSerializationInUrl(&'static str),
+    InvalidHeader(String),
+    UnknownAudience(String),
+    InvalidIssuer(String),
+    UnknownKid(String),
+    UnknownPolicy(String),
+    DisallowedAttributes(String, Vec<String>),
+    KeysWithDeterministicTokens,
+    StepUpWithReplayProtection,
+    AckFailurePageWithConcurrency,
+    InvalidFailureRate(f64),
+    InvalidDelayDistribution,
+    FaultInjectionDisabled,
+    UnsupportedCurve,
+    PublicKey(openssl::error::ErrorStack),
+    Jose(JoseError),
     Jwt(verder_helpen_jwt::Error),
 }
 
+impl Error {
+    /// Whether the error was caused by the request rather than by the
+    /// provider itself.
+    pub fn is_client_error(&self) -> bool {
+        matches!(
+            self,
+            Error::UnknownAttribute(_)
+                | Error::UnknownAttributes(_)
+                | Error::NoAttributes
+                | Error::UnknownAudience(_)
+                | Error::InvalidIssuer(_)
+                | Error::UnknownKid(_)
+                | Error::UnknownPolicy(_)
+                | Error::DisallowedAttributes(_, _)
+                | Error::FaultInjectionDisabled
+                | Error::DisallowedHost(_)
+                | Error::ContinuationOutsideBase(_)
+                | Error::InsecureUrl(_, _)
+                | Error::Url(_)
+        )
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::UnknownAttribute(_) => "unknown_attribute",
+            Error::UnknownAttributes(_) => "unknown_attributes",
+            Error::NoAttributes => "no_attributes",
+            Error::DisallowedHost(_) => "disallowed_host",
+            Error::ContinuationOutsideBase(_) => "continuation_outside_base",
+            Error::InsecureUrl(_, _) => "insecure_url",
+            Error::Url(_) => "url",
+            Error::MappingFile(_, _) => "mapping_file",
+            Error::LocaleFile(_, _) => "locale_file",
+            Error::UnknownLocale(_) => "unknown_locale",
+            Error::Figment(_) => "figment",
+            Error::Yaml(_) => "yaml",
+            Error::Json(_) => "json",
+            Error::UnsupportedAlgorithm(_, _) => "unsupported_algorithm",
+            Error::MissingKey(_) => "missing_key",
+            Error::IncompleteTls => "incomplete_tls",
+            Error::LoggingResultsWithHttps => "logging_results_with_https",
+            Error::JsonResultsWithHttps => "json_results_with_https",
+            Error::RequestedAttributesWithHttps => "requested_attributes_with_https",
+            Error::JsonSerializationInUrl(_) => "json_serialization_in_url",
+            Error::InvalidHeader(_) => "invalid_header",
+            Error::UnknownAudience(_) => "unknown_audience",
+            Error::InvalidIssuer(_) => "invalid_issuer",
+            Error::UnknownKid(_) => "unknown_kid",
+            Error::UnknownPolicy(_) => "unknown_policy",
+            Error::DisallowedAttributes(_, _) => "disallowed_attributes",
+            Error::KeysWithDeterministicTokens => "keys_with_deterministic_tokens",
+            Error::StepUpWithReplayProtection => "step_up_with_replay_protection",
+            Error::AckFailurePageWithConcurrency => "ack_failure_page_with_concurrency",
+            Error::InvalidFailureRate(_) => "invalid_failure_rate",
+            Error::InvalidDelayDistribution => "invalid_delay_distribution",
+            Error::FaultInjectionDisabled => "fault_injection_disabled",
+            Error::UnsupportedCurve => "unsupported_curve",
+            Error::PublicKey(_) => "public_key",
+            Error::Jose(_) => "jose",
+            Error::Jwt(_) => "jwt",
+        }
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Error {
+        Error::Url(e)
+    }
+}
+
 impl From<serde_yaml::Error> for Error {
     fn from(e: serde_yaml::Error) -> Error {
         Error::Yaml(e)
@@ -24,6 +170,18 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(e: openssl::error::ErrorStack) -> Error {
+        Error::PublicKey(e)
+    }
+}
+
+impl From<JoseError> for Error {
+    fn from(e: JoseError) -> Error {
+        Error::Jose(e)
+    }
+}
+
 impl From<verder_helpen_jwt::Error> for Error {
     fn from(e: verder_helpen_jwt::Error) -> Error {
         Error::Jwt(e)
@@ -34,8 +192,101 @@ impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::UnknownAttribute(a) => f.write_fmt(format_args!("Unknown attribute {}", a)),
+            Error::UnknownAttributes(a) => {
+                f.write_fmt(format_args!("Unknown attributes {}", a.join(", ")))
+            }
+            Error::NoAttributes => f.write_str("At least one attribute must be requested"),
+            Error::DisallowedHost(u) => f.write_fmt(format_args!("Host of {} is not allowed", u)),
+            Error::ContinuationOutsideBase(u) => f.write_fmt(format_args!(
+                "Continuation {} is outside of the continuation base url",
+                u
+            )),
+            Error::InsecureUrl(name, u) => f.write_fmt(format_args!(
+                "Rejected {} {}, only https urls are allowed",
+                name, u
+            )),
+            Error::Url(e) => e.fmt(f),
+            Error::MappingFile(path, e) => f.write_fmt(format_args!(
+                "Could not read attribute mapping file {}: {}",
+                path, e
+            )),
+            Error::LocaleFile(path, e) => {
+                f.write_fmt(format_args!("Could not read locale file {}: {}", path, e))
+            }
+            Error::UnknownLocale(locale) => f.write_fmt(format_args!(
+                "No messages for the default locale {}",
+                locale
+            )),
+            Error::Figment(e) => e.fmt(f),
             Error::Yaml(e) => e.fmt(f),
             Error::Json(e) => e.fmt(f),
+            Error::UnsupportedAlgorithm(algorithm, key_type) => f.write_fmt(format_args!(
+                "Algorithm {} can not be used with a key of type {}",
+                algorithm, key_type
+            )),
+            Error::MissingKey(name) => f.write_fmt(format_args!("Missing {}", name)),
+            Error::InvalidIssuer(i) => f.write_fmt(format_args!("Issuer {:?} is not a url", i)),
+            Error::UnknownAudience(a) => {
+                f.write_fmt(format_args!("No keys configured for audience {}", a))
+            }
+            Error::UnknownKid(k) => f.write_fmt(format_args!("No signing key with kid {}", k)),
+            Error::UnknownPolicy(p) => f.write_fmt(format_args!("Unknown attribute policy {}", p)),
+            Error::DisallowedAttributes(p, a) => f.write_fmt(format_args!(
+                "Attributes {} are not allowed by policy {}",
+                a.join(", "),
+                p
+            )),
+            Error::LoggingResultsWithHttps => f.write_str(
+                "log_auth_results can not be enabled together with require_https, results contain \
+                 personal data",
+            ),
+            Error::JsonResultsWithHttps => f.write_str(
+                "attr_url_format json can not be enabled together with require_https, results \
+                 would be sent unsigned and unencrypted",
+            ),
+            Error::RequestedAttributesWithHttps => f.write_str(
+                "include_requested_attributes can not be enabled together with require_https, it \
+                 is meant for debugging only",
+            ),
+            Error::JsonSerializationInUrl(delivery) => f.write_fmt(format_args!(
+                "jwe_serialization json can not be used with {} delivery, only compact tokens fit \
+                 in a url",
+                delivery
+            )),
+            Error::InvalidHeader(name) => {
+                f.write_fmt(format_args!("Invalid attr_url header {}", name))
+            }
+            Error::IncompleteTls => {
+                f.write_str("Both tls_cert_path and tls_key_path must be set to enable TLS")
+            }
+            Error::KeysWithDeterministicTokens => f.write_str(
+                "Deterministic tokens can not be enabled while signing or encryption keys are \
+                 configured",
+            ),
+            Error::StepUpWithReplayProtection => f.write_str(
+                "step_up can not be enabled together with replay_protection, the second round \
+                 reuses the browser url of the first",
+            ),
+            Error::AckFailurePageWithConcurrency => f.write_str(
+                "attr_url_ack_failure_page can not be enabled together with \
+                 attr_url_concurrency, the user is sent back before the attr_url responds",
+            ),
+            Error::InvalidFailureRate(rate) => f.write_fmt(format_args!(
+                "failure_rate must be between 0 and 1, not {}",
+                rate
+            )),
+            Error::InvalidDelayDistribution => f.write_str(
+                "response_delay_distribution needs min_ms <= max_ms, and a stddev_ms that is not \
+                 negative",
+            ),
+            Error::FaultInjectionDisabled => {
+                f.write_str("Corrupting tokens requires fault_injection to be enabled")
+            }
+            Error::UnsupportedCurve => f.write_str("The curve of the EC key is not supported"),
+            Error::PublicKey(e) => {
+                f.write_fmt(format_args!("Could not read the public key: {}", e))
+            }
+            Error::Jose(e) => e.fmt(f),
             Error::Jwt(e) => e.fmt(f),
         }
     }
@@ -44,23 +295,669 @@ impl Display for Error {
 impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self {
+            Error::Url(e) => Some(e),
+            Error::MappingFile(_, e) | Error::LocaleFile(_, e) => Some(e),
+            Error::Figment(e) => Some(e.as_ref()),
             Error::Yaml(e) => Some(e),
             Error::Json(e) => Some(e),
+            Error::PublicKey(e) => Some(e),
+            Error::Jose(e) => Some(e),
             Error::Jwt(e) => Some(e),
             _ => None,
         }
     }
 }
 
+/// Outcome of an authentication flow, as requested by the tester.
+#[derive(FromFormField, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultStatus {
+    #[default]
+    Success,
+    Failed,
+}
+
+impl ResultStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResultStatus::Success => "success",
+            ResultStatus::Failed => "failed",
+        }
+    }
+}
+
+/// How the result of an inline flow is delivered to the relying party.
+#[derive(FromFormField, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryMode {
+    /// In the query of a redirect to the continuation
+    #[default]
+    Redirect,
+    /// In a form posted to the continuation, like the SAML HTTP-POST binding
+    Post,
+    /// In the fragment of a redirect to the continuation, for single page
+    /// applications
+    Fragment,
+}
+
+impl DeliveryMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryMode::Redirect => "redirect",
+            DeliveryMode::Post => "post",
+            DeliveryMode::Fragment => "fragment",
+        }
+    }
+}
+
+/// Serialization of result tokens, as JOSE defines a compact and a JSON one.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenSerialization {
+    #[default]
+    Compact,
+    /// The flattened JSON serialization
+    Json,
+}
+
+impl TokenSerialization {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenSerialization::Compact => "compact",
+            TokenSerialization::Json => "json",
+        }
+    }
+}
+
+/// Shape of the attributes in the results the provider encodes itself. An
+/// object maps each attribute to its value, an array lists them as
+/// `{"name": ..., "value": ...}` entries sorted by name.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeResultShape {
+    #[default]
+    Object,
+    Array,
+}
+
+impl AttributeResultShape {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeResultShape::Object => "object",
+            AttributeResultShape::Array => "array",
+        }
+    }
+}
+
+/// Status of the redirects sending the user back to the relying party. A 307
+/// keeps the method of the request, a 303 always makes it a GET.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "u16")]
+pub enum RedirectStatus {
+    Found,
+    #[default]
+    SeeOther,
+    TemporaryRedirect,
+}
+
+impl TryFrom<u16> for RedirectStatus {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<RedirectStatus, String> {
+        match code {
+            302 => Ok(RedirectStatus::Found),
+            303 => Ok(RedirectStatus::SeeOther),
+            307 => Ok(RedirectStatus::TemporaryRedirect),
+            code => Err(format!(
+                "redirect_status must be 302, 303 or 307, not {}",
+                code
+            )),
+        }
+    }
+}
+
+impl RedirectStatus {
+    pub fn code(&self) -> u16 {
+        match self {
+            RedirectStatus::Found => 302,
+            RedirectStatus::SeeOther => 303,
+            RedirectStatus::TemporaryRedirect => 307,
+        }
+    }
+
+    pub fn redirect(&self, location: String) -> Redirect {
+        match self {
+            RedirectStatus::Found => Redirect::found(location),
+            RedirectStatus::SeeOther => Redirect::to(location),
+            RedirectStatus::TemporaryRedirect => Redirect::temporary(location),
+        }
+    }
+}
+
+/// What the user gets to see when a flow fails.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailedStatusBehavior {
+    /// Return to the relying party with the failed result
+    #[default]
+    Redirect,
+    /// Show an error page, and don't return to the relying party
+    ErrorPage,
+}
+
+impl FailedStatusBehavior {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailedStatusBehavior::Redirect => "redirect",
+            FailedStatusBehavior::ErrorPage => "error_page",
+        }
+    }
+}
+
+/// Distribution the response delay of a flow is sampled from. All values are
+/// in milliseconds, samples below zero are clamped to zero.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DelayDistribution {
+    /// Always response_delay_ms
+    #[default]
+    Fixed,
+    /// Uniformly between min_ms and max_ms, inclusive
+    Uniform { min_ms: u64, max_ms: u64 },
+    /// Normally distributed around mean_ms
+    Normal { mean_ms: f64, stddev_ms: f64 },
+}
+
+/// Page shown by interactive flows before completing.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InteractiveMode {
+    /// A form for adjusting the attribute values
+    #[default]
+    Form,
+    /// A consent screen naming the relying party, where allowing completes
+    /// the flow and denying cancels it
+    Consent,
+}
+
+impl InteractiveMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InteractiveMode::Form => "form",
+            InteractiveMode::Consent => "consent",
+        }
+    }
+}
+
+/// Method of the form delivering results in the post delivery mode.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FormMethod {
+    #[default]
+    Post,
+    Get,
+}
+
+impl FormMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FormMethod::Post => "post",
+            FormMethod::Get => "get",
+        }
+    }
+}
+
+/// HTTP method of the requests delivering results to the attr_url.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AttrUrlMethod {
+    #[default]
+    Post,
+    Put,
+}
+
+impl AttrUrlMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttrUrlMethod::Post => "POST",
+            AttrUrlMethod::Put => "PUT",
+        }
+    }
+
+    pub fn method(&self) -> Method {
+        match self {
+            AttrUrlMethod::Post => Method::POST,
+            AttrUrlMethod::Put => Method::PUT,
+        }
+    }
+}
+
+/// Format of the results posted to the attr_url.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AttrUrlFormat {
+    /// The signed and encrypted token, as `application/jwt`
+    #[default]
+    Jwt,
+    /// The plain `AuthResult` as `application/json`. Insecure, only for
+    /// testing relying parties that can't process tokens.
+    Json,
+}
+
+impl AttrUrlFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttrUrlFormat::Jwt => "jwt",
+            AttrUrlFormat::Json => "json",
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_result_parameter_name() -> String {
+    "result".to_string()
+}
+
+fn default_retry_base_delay_ms() -> u64 {
+    100
+}
+
+fn default_shutdown_grace_seconds() -> u64 {
+    5
+}
+
+fn default_active_flow_ttl_seconds() -> u64 {
+    60 * 60
+}
+
+fn default_max_batch_size() -> usize {
+    100
+}
+
+fn default_idempotency_ttl_seconds() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_short_url_ttl_seconds() -> u64 {
+    15 * 60
+}
+
+/// The lifetime of the tokens produced by `verder_helpen_jwt`.
+fn default_token_lifetime_seconds() -> u64 {
+    5 * 60
+}
+
+fn default_replay_protection_ttl_seconds() -> u64 {
+    60 * 60
+}
+
+fn default_step_up_ttl_seconds() -> u64 {
+    60 * 60
+}
+
+fn default_max_request_body_bytes() -> u64 {
+    64 * 1024
+}
+
+fn default_locale() -> String {
+    "nl".to_string()
+}
+
+/// Source of attribute values that differ for every flow.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Generator {
+    /// A random v4 uuid
+    Uuid,
+    /// A number counting up from 1, shared by all generated attributes
+    Counter,
+    /// The current unix timestamp
+    Timestamp,
+}
+
+/// Configured value of an attribute. As results carry a single string per
+/// attribute, multiple values are encoded as a JSON array in that string.
+/// Generated values are produced anew every time the attributes are mapped,
+/// so the value on a confirmation page is not the one in the result.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Single(String),
+    Multiple(Vec<String>),
+    Generated { generator: Generator },
+}
+
+impl AttributeValue {
+    fn to_result_value(&self, counter: &AtomicU64) -> Result<String, Error> {
+        match self {
+            AttributeValue::Single(value) => Ok(value.clone()),
+            AttributeValue::Multiple(values) => Ok(serde_json::to_string(values)?),
+            AttributeValue::Generated { generator } => Ok(match generator {
+                Generator::Uuid => uuid::Uuid::new_v4().to_string(),
+                Generator::Counter => (counter.fetch_add(1, Ordering::Relaxed) + 1).to_string(),
+                Generator::Timestamp => session::now().to_string(),
+            }),
+        }
+    }
+}
+
+/// Step in the transformation of an attribute value, applied after the
+/// value has been looked up.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transform {
+    Identity,
+    Upper,
+    Lower,
+    /// Lowercase hex encoding of the SHA-256 digest of the value
+    Sha256Hex,
+    Prefix(String),
+}
+
+impl Transform {
+    fn apply(&self, value: String) -> String {
+        match self {
+            Transform::Identity => value,
+            Transform::Upper => value.to_uppercase(),
+            Transform::Lower => value.to_lowercase(),
+            Transform::Sha256Hex => Sha256::digest(value.as_bytes())
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            Transform::Prefix(prefix) => format!("{}{}", prefix, value),
+        }
+    }
+}
+
+/// Keys for the results of a single audience.
+#[derive(Deserialize, Debug)]
+struct RawAudienceKeys {
+    #[serde(default)]
+    signing_algorithm: Option<SigningAlgorithm>,
+    #[serde(default)]
+    encryption_algorithm: Option<EncryptionAlgorithm>,
+    encryption_pubkey: EncryptionKeyConfig,
+    signing_privkey: SignKeyConfig,
+}
+
+/// Counterparts of the default keys, needed to inspect results.
+#[derive(Deserialize, Debug)]
+struct RawInspectKeys {
+    encryption_privkey: EncryptionKeyConfig,
+    signing_pubkey: SignKeyConfig,
+}
+
 #[derive(Deserialize, Debug)]
 struct RawConfig {
     server_url: String,
     internal_url: String,
-    attributes: HashMap<String, String>,
+    #[serde(default)]
+    attributes: HashMap<String, AttributeValue>,
+    #[serde(default)]
+    attribute_mapping_file: Option<String>,
+    #[serde(default)]
+    attribute_defaults: HashMap<String, AttributeValue>,
+    #[serde(default)]
+    override_attributes: Option<HashMap<String, AttributeValue>>,
+    /// Transformations per attribute, applied in order.
+    #[serde(default)]
+    attribute_transforms: HashMap<String, Vec<Transform>>,
+    /// Other names relying parties use for attributes, mapped to the name
+    /// they are configured under.
+    #[serde(default)]
+    attribute_aliases: HashMap<String, String>,
+    /// Return aliased attributes under the requested alias, instead of under
+    /// the name they are configured under.
+    #[serde(default = "bool::default")]
+    echo_attribute_aliases: bool,
+    /// Named sets of attributes a relying party may request, selected with
+    /// the policy of a start request.
+    #[serde(default)]
+    attribute_policies: HashMap<String, HashSet<String>>,
+    #[serde(default = "bool::default")]
+    omit_missing_attributes: bool,
+    /// Complete flows requesting attributes without a value with a failed
+    /// result, instead of rejecting them at the start. Takes precedence over
+    /// omit_missing_attributes.
+    #[serde(default = "bool::default")]
+    fail_on_missing_attributes: bool,
+    #[serde(default = "bool::default")]
+    allow_empty_attributes: bool,
+    /// Accept the attributes segment of browser urls as a comma separated
+    /// list as well, as sent by some older clients.
+    #[serde(default = "bool::default")]
+    lenient_attribute_parsing: bool,
     #[serde(default = "bool::default")]
     with_session: bool,
-    encryption_pubkey: EncryptionKeyConfig,
-    signing_privkey: SignKeyConfig,
+    #[serde(default = "bool::default")]
+    interactive: bool,
+    #[serde(default)]
+    interactive_mode: InteractiveMode,
+    /// Locale of the pages when the user accepts none of the supported
+    /// ones.
+    #[serde(default = "default_locale")]
+    default_locale: String,
+    /// Message bundles by locale, read like the attribute mapping file.
+    /// Bundles for `nl` and `en` are built in, a file for one of those
+    /// replaces only the messages it has.
+    #[serde(default)]
+    locale_files: HashMap<String, String>,
+    #[serde(default = "bool::default")]
+    allow_get_start: bool,
+    /// Zero is rejected, leave it out to not limit starts at all.
+    #[serde(default)]
+    max_starts_per_minute: Option<NonZeroU32>,
+    /// Reject new flows while this many flows are started but not yet
+    /// completed in the browser, to test backpressure. Flows that are not
+    /// completed stop counting after active_flow_ttl_seconds.
+    #[serde(default)]
+    max_active_flows: Option<usize>,
+    #[serde(default = "default_active_flow_ttl_seconds")]
+    active_flow_ttl_seconds: u64,
+    #[serde(default = "default_max_batch_size")]
+    max_batch_size: usize,
+    /// How long the response to a start request with an `Idempotency-Key`
+    /// is returned for retries with the same key.
+    #[serde(default = "default_idempotency_ttl_seconds")]
+    idempotency_ttl_seconds: u64,
+    #[serde(default = "bool::default")]
+    short_urls: bool,
+    #[serde(default = "default_short_url_ttl_seconds")]
+    short_url_ttl_seconds: u64,
+    /// Pad the base64 segments of the urls handed out, for clients that
+    /// expect padding. The segments are unpadded by default, as some
+    /// relying parties reject `=` in paths.
+    #[serde(default = "bool::default")]
+    url_base64_padding: bool,
+    /// Base the urls handed out to browsers on the host requests are sent
+    /// to, instead of server_url, for environments where that varies.
+    #[serde(default = "bool::default")]
+    derive_server_url_from_host: bool,
+    /// Reject a second use of the browser urls of a flow. Off by default, as
+    /// it stops testers from simply reloading a page.
+    #[serde(default = "bool::default")]
+    replay_protection: bool,
+    #[serde(default = "default_replay_protection_ttl_seconds")]
+    replay_protection_ttl_seconds: u64,
+    /// Complete flows in two rounds, for testing step-up authentication.
+    /// The first browser hit of a flow returns a low assurance result, with
+    /// only the step_up_attributes that were requested, and adds
+    /// `step_up=required` to the continuation. Opening the same browser url
+    /// again, within step_up_ttl_seconds, returns the full result, after
+    /// which the next hit starts over with the first round. Flows started
+    /// before flows had ids always get the full result.
+    #[serde(default = "bool::default")]
+    step_up: bool,
+    #[serde(default)]
+    step_up_attributes: HashSet<String>,
+    #[serde(default = "default_step_up_ttl_seconds")]
+    step_up_ttl_seconds: u64,
+    /// Limit on the size of start request bodies. An attribute name takes
+    /// its length plus a few bytes of JSON, so the default of 64 KiB leaves
+    /// room for thousands of attributes.
+    #[serde(default = "default_max_request_body_bytes")]
+    max_request_body_bytes: u64,
+    #[serde(default = "default_true")]
+    encrypt_results: bool,
+    #[serde(default = "default_token_lifetime_seconds")]
+    token_lifetime_seconds: u64,
+    /// Set the `nbf` claim this long before the time of issue.
+    #[serde(default)]
+    token_not_before_skew_seconds: Option<u64>,
+    /// The `iss` claim of result tokens, which flows can override.
+    #[serde(default)]
+    issuer: Option<String>,
+    #[serde(default)]
+    session_ttl_seconds: Option<u64>,
+    #[serde(default = "default_shutdown_grace_seconds")]
+    shutdown_grace_seconds: u64,
+    /// Maximum number of attr_url deliveries in flight. With a maximum, the
+    /// user is sent back without waiting for the delivery of their result,
+    /// so it can't be combined with attr_url_ack_failure_page.
+    #[serde(default)]
+    attr_url_concurrency: Option<NonZeroUsize>,
+    #[serde(default)]
+    cors: Option<CorsConfig>,
+    #[serde(default)]
+    security_headers: Option<SecurityHeadersConfig>,
+    /// Log every request, not just the steps of flows.
+    #[serde(default = "bool::default")]
+    log_requests: bool,
+    #[serde(default)]
+    request_log_level: RequestLogLevel,
+    #[serde(default)]
+    admin_token: Option<String>,
+    /// Serve https directly. When set, server_url should use the https
+    /// scheme as well, as it is the base of the urls we hand out.
+    #[serde(default)]
+    tls_cert_path: Option<String>,
+    #[serde(default)]
+    tls_key_path: Option<String>,
+    /// Base that relative continuations are resolved against. Its path is
+    /// taken as a directory, so `/app` allows `/app/...` but not
+    /// `/application`.
+    #[serde(default)]
+    continuation_base_url: Option<String>,
+    #[serde(default)]
+    allowed_continuation_hosts: Vec<String>,
+    #[serde(default = "bool::default")]
+    require_https: bool,
+    #[serde(default = "bool::default")]
+    log_auth_results: bool,
+    /// Add the attributes as requested, before mapping, to results, under
+    /// `REQUESTED_ATTRIBUTES`. For debugging mappings only.
+    #[serde(default = "bool::default")]
+    include_requested_attributes: bool,
+    /// Include result tokens in the logs of redirects and posts. Tokens are
+    /// signed identity assertions, so they are redacted by default.
+    #[serde(default = "bool::default")]
+    log_tokens: bool,
+    #[serde(default)]
+    default_status: ResultStatus,
+    #[serde(default)]
+    failed_status_behavior: FailedStatusBehavior,
+    #[serde(default)]
+    response_delay_ms: u64,
+    #[serde(default)]
+    redirect_status: RedirectStatus,
+    #[serde(default)]
+    response_delay_distribution: DelayDistribution,
+    /// Fraction of flows that fail at random, regardless of the requested
+    /// status.
+    #[serde(default)]
+    failure_rate: f64,
+    /// Seed for the random decisions, so they are the same on every run.
+    /// Can't be changed by reloading the configuration.
+    #[serde(default)]
+    random_seed: Option<u64>,
+    #[serde(default = "default_result_parameter_name")]
+    result_parameter_name: String,
+    /// Put the result in place of a `{result}` placeholder in the
+    /// continuation, when it has one, instead of in the query.
+    #[serde(default = "bool::default")]
+    continuation_template: bool,
+    #[serde(default)]
+    delivery_mode: DeliveryMode,
+    #[serde(default = "default_result_parameter_name")]
+    post_field_name: String,
+    #[serde(default)]
+    post_form_method: FormMethod,
+    #[serde(default)]
+    attr_url_format: AttrUrlFormat,
+    #[serde(default)]
+    attr_url_method: AttrUrlMethod,
+    /// Serialization of the tokens posted to the attr_url, or delivered in a
+    /// form. Tokens in the query or fragment of a redirect must be compact.
+    #[serde(default)]
+    jwe_serialization: TokenSerialization,
+    /// Shape of the attributes in result tokens and JSON attr_url bodies.
+    /// Arrays are only understood by relying parties decoding the claims
+    /// themselves, verder_helpen_jwt expects an object.
+    #[serde(default)]
+    attribute_result_shape: AttributeResultShape,
+    /// Require a 2xx response to posts to the attr_url, and log its body.
+    #[serde(default = "bool::default")]
+    attr_url_expect_ack: bool,
+    /// Show the user an error page when the attr_url did not acknowledge
+    /// the result, instead of returning to the relying party.
+    #[serde(default = "bool::default")]
+    attr_url_ack_failure_page: bool,
+    /// Delay of the post to the attr_url, on top of response_delay_ms. The
+    /// user is sent back first, so the result arrives after they returned.
+    #[serde(default)]
+    attr_url_delay_ms: u64,
+    #[serde(default)]
+    attr_url_retries: u32,
+    #[serde(default = "default_retry_base_delay_ms")]
+    attr_url_retry_base_delay_ms: u64,
+    #[serde(default)]
+    attr_url_timeout_ms: Option<u64>,
+    #[serde(default)]
+    attr_url_user_agent: Option<String>,
+    /// Content type of the tokens posted to the attr_url, instead of
+    /// `application/jwt`, or `application/jose+json` for the JSON
+    /// serialization. JSON results are always posted as `application/json`.
+    #[serde(default)]
+    attr_url_content_type: Option<String>,
+    #[serde(default)]
+    attr_url_extra_headers: HashMap<String, String>,
+    #[serde(default)]
+    signing_algorithm: Option<SigningAlgorithm>,
+    #[serde(default)]
+    encryption_algorithm: Option<EncryptionAlgorithm>,
+    #[serde(default)]
+    encryption_encoding: Option<ContentEncoding>,
+    #[serde(default = "bool::default")]
+    deterministic_tokens: bool,
+    /// Allow flows to ask for corrupted tokens.
+    #[serde(default = "bool::default")]
+    fault_injection: bool,
+    /// Serve the endpoints under /test, such as the attr_url sink.
+    #[serde(default = "bool::default")]
+    test_endpoints: bool,
+    #[serde(default)]
+    encryption_pubkey: Option<EncryptionKeyConfig>,
+    #[serde(default)]
+    signing_privkey: Option<SignKeyConfig>,
+    /// Signing keys by kid, for testing key rotation. When configured, the
+    /// key with the active kid signs the results of the default audience
+    /// instead of signing_privkey, with its kid in the header. All of them
+    /// are published in the JWKS.
+    #[serde(default)]
+    signing_keys: HashMap<String, SignKeyConfig>,
+    #[serde(default)]
+    active_signing_kid: Option<String>,
+    #[serde(default)]
+    audiences: HashMap<String, RawAudienceKeys>,
+    #[serde(default)]
+    inspect_keys: Option<RawInspectKeys>,
+    /// Public key of the broker. When set, session updates must carry a
+    /// signature, see `session::SignedUpdate`.
+    #[serde(default)]
+    session_update_verify_key: Option<SignKeyConfig>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -68,10 +965,213 @@ struct RawConfig {
 pub struct Config {
     server_url: String,
     internal_url: String,
-    attributes: HashMap<String, String>,
+    attributes: HashMap<String, AttributeValue>,
+    attribute_defaults: HashMap<String, AttributeValue>,
+    override_attributes: Option<HashMap<String, AttributeValue>>,
+    /// Last value of the counter generator. Reloading the configuration
+    /// starts counting from 1 again.
+    generator_counter: AtomicU64,
+    attribute_transforms: HashMap<String, Vec<Transform>>,
+    attribute_aliases: HashMap<String, String>,
+    echo_attribute_aliases: bool,
+    attribute_policies: HashMap<String, HashSet<String>>,
+    omit_missing_attributes: bool,
+    fail_on_missing_attributes: bool,
+    allow_empty_attributes: bool,
+    lenient_attribute_parsing: bool,
     with_session: bool,
-    encrypter: Box<dyn JweEncrypter>,
-    signer: Box<dyn JwsSigner>,
+    interactive: bool,
+    interactive_mode: InteractiveMode,
+    locales: Locales,
+    allow_get_start: bool,
+    max_starts_per_minute: Option<NonZeroU32>,
+    max_active_flows: Option<usize>,
+    active_flow_ttl_seconds: u64,
+    max_batch_size: usize,
+    idempotency_ttl_seconds: u64,
+    short_urls: bool,
+    short_url_ttl_seconds: u64,
+    url_base64_padding: bool,
+    derive_server_url_from_host: bool,
+    replay_protection: bool,
+    replay_protection_ttl_seconds: u64,
+    step_up: bool,
+    step_up_attributes: HashSet<String>,
+    step_up_ttl_seconds: u64,
+    max_request_body_bytes: u64,
+    encrypt_results: bool,
+    token_lifetime_seconds: u64,
+    token_not_before_skew_seconds: Option<u64>,
+    issuer: Option<String>,
+    session_ttl_seconds: Option<u64>,
+    shutdown_grace_seconds: u64,
+    attr_url_concurrency: Option<NonZeroUsize>,
+    cors: Option<CorsConfig>,
+    security_headers: Option<SecurityHeadersConfig>,
+    log_requests: bool,
+    request_log_level: RequestLogLevel,
+    admin_token: Option<String>,
+    tls_paths: Option<(String, String)>,
+    continuation_base_url: Option<Url>,
+    allowed_continuation_hosts: Vec<String>,
+    require_https: bool,
+    log_auth_results: bool,
+    include_requested_attributes: bool,
+    log_tokens: bool,
+    default_status: ResultStatus,
+    failed_status_behavior: FailedStatusBehavior,
+    response_delay_ms: u64,
+    redirect_status: RedirectStatus,
+    response_delay_distribution: DelayDistribution,
+    failure_rate: f64,
+    random_seed: Option<u64>,
+    result_parameter_name: String,
+    continuation_template: bool,
+    delivery_mode: DeliveryMode,
+    post_field_name: String,
+    post_form_method: FormMethod,
+    attr_url_format: AttrUrlFormat,
+    attr_url_method: AttrUrlMethod,
+    jwe_serialization: TokenSerialization,
+    attribute_result_shape: AttributeResultShape,
+    attr_url_expect_ack: bool,
+    attr_url_ack_failure_page: bool,
+    attr_url_delay_ms: u64,
+    attr_url_retries: u32,
+    attr_url_retry_base_delay_ms: u64,
+    attr_url_timeout_ms: Option<u64>,
+    attr_url_headers: HeaderMap,
+    attr_url_content_type: Option<HeaderValue>,
+    signing_algorithm: Option<SigningAlgorithm>,
+    encryption_algorithm: Option<EncryptionAlgorithm>,
+    encryption_encoding: Option<ContentEncoding>,
+    tokens: TokenKeys,
+    key_rotation: Option<KeyRotation>,
+    fault_injection: bool,
+    test_endpoints: bool,
+    jwks: Option<serde_json::Value>,
+    audiences: HashMap<String, TokenKeys>,
+    inspect_keys: Option<InspectKeys>,
+    session_update_verifier: Option<Box<dyn JwsVerifier>>,
+}
+
+/// Keys to turn results produced with the default keys back into their
+/// contents.
+#[derive(Debug)]
+pub struct InspectKeys {
+    pub verifier: Box<dyn JwsVerifier>,
+    pub decrypter: Box<dyn JweDecrypter>,
+}
+
+/// How result tokens are produced.
+#[derive(Debug)]
+pub enum TokenKeys {
+    /// Signed, and possibly encrypted, with the configured keys.
+    Keys {
+        signer: Box<dyn JwsSigner>,
+        encrypter: Box<dyn JweEncrypter>,
+    },
+    /// Fixed placeholder tokens, see `token::deterministic_token`. Only
+    /// possible when no keys are configured, so a configuration with real
+    /// keys can never hand out unsigned results.
+    Deterministic,
+}
+
+/// Signing keys of the default audience when rotating keys. The active kid
+/// can be switched at runtime, until the configuration is reloaded.
+pub struct KeyRotation {
+    signers: HashMap<String, Box<dyn JwsSigner>>,
+    active: Mutex<String>,
+}
+
+impl std::fmt::Debug for KeyRotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyRotation")
+            .field("kids", &self.kids())
+            .field("active", &self.active_kid())
+            .finish()
+    }
+}
+
+impl KeyRotation {
+    /// The kids of all keys, sorted.
+    pub fn kids(&self) -> Vec<&str> {
+        let mut kids: Vec<&str> = self.signers.keys().map(String::as_str).collect();
+        kids.sort_unstable();
+        kids
+    }
+
+    pub fn active_kid(&self) -> String {
+        self.active
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The active kid with its signer.
+    pub fn active_signer(&self) -> (String, &dyn JwsSigner) {
+        let kid = self.active_kid();
+        // Only kids of configured keys are ever made active
+        let signer = self.signers[&kid].as_ref();
+        (kid, signer)
+    }
+
+    /// Make another key the active one, returning the kid it replaces.
+    pub fn activate(&self, kid: &str) -> Result<String, Error> {
+        if !self.signers.contains_key(kid) {
+            return Err(Error::UnknownKid(kid.to_string()));
+        }
+        let mut active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(std::mem::replace(&mut active, kid.to_string()))
+    }
+}
+
+/// Placeholder shown instead of secrets and key material.
+const REDACTED: &str = "[redacted]";
+
+/// Load an attribute mapping from a JSON, YAML or TOML file, based on its
+/// extension. Files without a known extension are parsed as TOML.
+fn load_attribute_mapping(path: &str) -> Result<HashMap<String, AttributeValue>, Error> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| Error::MappingFile(path.to_string(), e))?;
+    parse_data_file(path, &contents)
+}
+
+/// Issuers are usually the url of the provider, anything that parses as a
+/// url is accepted.
+pub fn verify_issuer(issuer: &str) -> Result<(), Error> {
+    match Url::parse(issuer) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::InvalidIssuer(issuer.to_string())),
+    }
+}
+
+fn load_messages(path: &str) -> Result<Messages, Error> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| Error::LocaleFile(path.to_string(), e))?;
+    parse_data_file(path, &contents)
+}
+
+/// Parse JSON, YAML or TOML, depending on the extension of the file.
+fn parse_data_file<T: DeserializeOwned>(path: &str, contents: &str) -> Result<T, Error> {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("json") => Ok(serde_json::from_str(contents)?),
+        Some("yaml") | Some("yml") => Ok(serde_yaml::from_str(contents)?),
+        _ => Figment::from(Toml::string(contents))
+            .extract()
+            .map_err(|e| Error::Figment(Box::new(e))),
+    }
+}
+
+/// The base path gets a trailing slash, so relative continuations are joined
+/// onto it instead of replacing its last segment.
+fn parse_continuation_base(base: &str) -> Result<Url, url::ParseError> {
+    let mut base = Url::parse(base)?;
+    if !base.path().ends_with('/') {
+        let path = format!("{}/", base.path());
+        base.set_path(&path);
+    }
+    Ok(base)
 }
 
 // This tryfrom can be removed once try_from for fields lands in serde
@@ -79,47 +1179,466 @@ impl TryFrom<RawConfig> for Config {
     type Error = Error;
 
     fn try_from(config: RawConfig) -> Result<Config, Error> {
+        let mut attributes = match &config.attribute_mapping_file {
+            Some(path) => load_attribute_mapping(path)?,
+            None => HashMap::new(),
+        };
+        // Inline attributes take precedence over those from the file
+        attributes.extend(config.attributes);
+
+        let mut bundles = locale::builtin()?;
+        for (name, path) in &config.locale_files {
+            bundles
+                .entry(name.clone())
+                .or_default()
+                .extend(load_messages(path)?);
+        }
+        let locales = Locales::new(config.default_locale.clone(), bundles)
+            .ok_or_else(|| Error::UnknownLocale(config.default_locale.clone()))?;
+
+        // Requiring https marks a production like deployment, where personal
+        // data should not end up in the logs
+        if config.log_auth_results && config.require_https {
+            return Err(Error::LoggingResultsWithHttps);
+        }
+        if config.attr_url_format == AttrUrlFormat::Json && config.require_https {
+            return Err(Error::JsonResultsWithHttps);
+        }
+        if config.include_requested_attributes && config.require_https {
+            return Err(Error::RequestedAttributesWithHttps);
+        }
+        if let Some(issuer) = &config.issuer {
+            verify_issuer(issuer)?;
+        }
+        if config.step_up && config.replay_protection {
+            return Err(Error::StepUpWithReplayProtection);
+        }
+        if config.attr_url_concurrency.is_some()
+            && config.attr_url_expect_ack
+            && config.attr_url_ack_failure_page
+        {
+            return Err(Error::AckFailurePageWithConcurrency);
+        }
+        if !(0.0..=1.0).contains(&config.failure_rate) {
+            return Err(Error::InvalidFailureRate(config.failure_rate));
+        }
+        match config.response_delay_distribution {
+            DelayDistribution::Uniform { min_ms, max_ms } if min_ms > max_ms => {
+                return Err(Error::InvalidDelayDistribution)
+            }
+            DelayDistribution::Normal { stddev_ms, .. }
+                if stddev_ms.is_nan() || stddev_ms < 0.0 =>
+            {
+                return Err(Error::InvalidDelayDistribution)
+            }
+            _ => {}
+        }
+
+        let mut attr_url_headers = HeaderMap::new();
+        for (name, value) in &config.attr_url_extra_headers {
+            attr_url_headers.insert(
+                HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| Error::InvalidHeader(name.clone()))?,
+                HeaderValue::from_str(value).map_err(|_| Error::InvalidHeader(name.clone()))?,
+            );
+        }
+        if let Some(user_agent) = &config.attr_url_user_agent {
+            attr_url_headers.insert(
+                USER_AGENT,
+                HeaderValue::from_str(user_agent)
+                    .map_err(|_| Error::InvalidHeader(USER_AGENT.to_string()))?,
+            );
+        }
+
+        let attr_url_content_type = config
+            .attr_url_content_type
+            .as_deref()
+            .map(|content_type| {
+                HeaderValue::from_str(content_type)
+                    .map_err(|_| Error::InvalidHeader(CONTENT_TYPE.to_string()))
+            })
+            .transpose()?;
+
+        let tls_paths = match (config.tls_cert_path, config.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => Some((cert_path, key_path)),
+            (None, None) => None,
+            _ => return Err(Error::IncompleteTls),
+        };
+
+        let mut jwks = None;
+        let mut key_rotation = None;
+        let tokens = if config.deterministic_tokens {
+            if config.signing_privkey.is_some()
+                || !config.signing_keys.is_empty()
+                || config.encryption_pubkey.is_some()
+                || !config.audiences.is_empty()
+            {
+                return Err(Error::KeysWithDeterministicTokens);
+            }
+            TokenKeys::Deterministic
+        } else {
+            let signing_privkey = config
+                .signing_privkey
+                .ok_or(Error::MissingKey("signing_privkey"))?;
+            let encryption_pubkey = config
+                .encryption_pubkey
+                .ok_or(Error::MissingKey("encryption_pubkey"))?;
+            let mut public = jwks::public_jwks(
+                &signing_privkey,
+                config.signing_algorithm,
+                &encryption_pubkey,
+                config.encryption_algorithm,
+            )?;
+            if !config.signing_keys.is_empty() {
+                let active = config
+                    .active_signing_kid
+                    .clone()
+                    .ok_or(Error::MissingKey("active_signing_kid"))?;
+                let mut signers = HashMap::new();
+                for (kid, key) in config.signing_keys {
+                    if let Some(keys) = public["keys"].as_array_mut() {
+                        keys.push(jwks::named_jwk(&key, &kid, config.signing_algorithm)?);
+                    }
+                    signers.insert(kid, keys::signer(key, config.signing_algorithm)?);
+                }
+                if !signers.contains_key(&active) {
+                    return Err(Error::UnknownKid(active));
+                }
+                key_rotation = Some(KeyRotation {
+                    signers,
+                    active: Mutex::new(active),
+                });
+            }
+            jwks = Some(public);
+            TokenKeys::Keys {
+                signer: keys::signer(signing_privkey, config.signing_algorithm)?,
+                encrypter: keys::encrypter(encryption_pubkey, config.encryption_algorithm)?,
+            }
+        };
+
         Ok(Config {
             server_url: config.server_url,
             internal_url: config.internal_url,
-            attributes: config.attributes,
+            attributes,
+            attribute_defaults: config.attribute_defaults,
+            override_attributes: config.override_attributes,
+            generator_counter: AtomicU64::new(0),
+            attribute_transforms: config.attribute_transforms,
+            attribute_aliases: config.attribute_aliases,
+            echo_attribute_aliases: config.echo_attribute_aliases,
+            attribute_policies: config.attribute_policies,
+            omit_missing_attributes: config.omit_missing_attributes,
+            fail_on_missing_attributes: config.fail_on_missing_attributes,
+            allow_empty_attributes: config.allow_empty_attributes,
+            lenient_attribute_parsing: config.lenient_attribute_parsing,
             with_session: config.with_session,
-            encrypter: Box::<dyn JweEncrypter>::try_from(config.encryption_pubkey)?,
-            signer: Box::<dyn JwsSigner>::try_from(config.signing_privkey)?,
+            interactive: config.interactive,
+            interactive_mode: config.interactive_mode,
+            locales,
+            allow_get_start: config.allow_get_start,
+            max_starts_per_minute: config.max_starts_per_minute,
+            max_active_flows: config.max_active_flows,
+            active_flow_ttl_seconds: config.active_flow_ttl_seconds,
+            max_batch_size: config.max_batch_size,
+            idempotency_ttl_seconds: config.idempotency_ttl_seconds,
+            short_urls: config.short_urls,
+            url_base64_padding: config.url_base64_padding,
+            short_url_ttl_seconds: config.short_url_ttl_seconds,
+            derive_server_url_from_host: config.derive_server_url_from_host,
+            replay_protection: config.replay_protection,
+            replay_protection_ttl_seconds: config.replay_protection_ttl_seconds,
+            step_up: config.step_up,
+            step_up_attributes: config.step_up_attributes,
+            step_up_ttl_seconds: config.step_up_ttl_seconds,
+            max_request_body_bytes: config.max_request_body_bytes,
+            encrypt_results: config.encrypt_results,
+            token_lifetime_seconds: config.token_lifetime_seconds,
+            token_not_before_skew_seconds: config.token_not_before_skew_seconds,
+            issuer: config.issuer,
+            session_ttl_seconds: config.session_ttl_seconds,
+            shutdown_grace_seconds: config.shutdown_grace_seconds,
+            attr_url_concurrency: config.attr_url_concurrency,
+            cors: config.cors,
+            security_headers: config.security_headers,
+            log_requests: config.log_requests,
+            request_log_level: config.request_log_level,
+            admin_token: config.admin_token,
+            tls_paths,
+            continuation_base_url: config
+                .continuation_base_url
+                .as_deref()
+                .map(parse_continuation_base)
+                .transpose()?,
+            allowed_continuation_hosts: config.allowed_continuation_hosts,
+            require_https: config.require_https,
+            log_auth_results: config.log_auth_results,
+            include_requested_attributes: config.include_requested_attributes,
+            log_tokens: config.log_tokens,
+            default_status: config.default_status,
+            failed_status_behavior: config.failed_status_behavior,
+            response_delay_ms: config.response_delay_ms,
+            redirect_status: config.redirect_status,
+            response_delay_distribution: config.response_delay_distribution,
+            failure_rate: config.failure_rate,
+            random_seed: config.random_seed,
+            result_parameter_name: config.result_parameter_name,
+            continuation_template: config.continuation_template,
+            delivery_mode: config.delivery_mode,
+            post_field_name: config.post_field_name,
+            post_form_method: config.post_form_method,
+            attr_url_format: config.attr_url_format,
+            attr_url_method: config.attr_url_method,
+            jwe_serialization: config.jwe_serialization,
+            attribute_result_shape: config.attribute_result_shape,
+            attr_url_expect_ack: config.attr_url_expect_ack,
+            attr_url_ack_failure_page: config.attr_url_ack_failure_page,
+            attr_url_delay_ms: config.attr_url_delay_ms,
+            attr_url_retries: config.attr_url_retries,
+            attr_url_retry_base_delay_ms: config.attr_url_retry_base_delay_ms,
+            attr_url_timeout_ms: config.attr_url_timeout_ms,
+            attr_url_headers,
+            attr_url_content_type,
+            signing_algorithm: config.signing_algorithm,
+            encryption_algorithm: config.encryption_algorithm,
+            encryption_encoding: config.encryption_encoding,
+            tokens,
+            fault_injection: config.fault_injection,
+            test_endpoints: config.test_endpoints,
+            jwks,
+            key_rotation,
+            audiences: config
+                .audiences
+                .into_iter()
+                .map(|(audience, keys)| {
+                    Ok((
+                        audience,
+                        TokenKeys::Keys {
+                            signer: keys::signer(keys.signing_privkey, keys.signing_algorithm)?,
+                            encrypter: keys::encrypter(
+                                keys.encryption_pubkey,
+                                keys.encryption_algorithm,
+                            )?,
+                        },
+                    ))
+                })
+                .collect::<Result<_, Error>>()?,
+            inspect_keys: match config.inspect_keys {
+                Some(keys) => Some(InspectKeys {
+                    verifier: keys::verifier(keys.signing_pubkey, config.signing_algorithm)?,
+                    decrypter: keys::decrypter(
+                        keys.encryption_privkey,
+                        config.encryption_algorithm,
+                    )?,
+                }),
+                None => None,
+            },
+            session_update_verifier: match config.session_update_verify_key {
+                Some(key) => Some(keys::verifier(key, None)?),
+                None => None,
+            },
         })
     }
 }
 
 impl Config {
-    pub fn verify_attributes(&self, attributes: &[String]) -> Result<(), Error> {
-        for attribute in attributes.iter() {
-            self.attributes
-                .get(attribute)
-                .ok_or_else(|| Error::UnknownAttribute(attribute.clone()))?;
+    /// The name an attribute is configured under, resolving aliases.
+    fn canonical_attribute<'a>(&'a self, attribute: &'a str) -> &'a str {
+        self.attribute_aliases
+            .get(attribute)
+            .map_or(attribute, String::as_str)
+    }
+
+    /// The configured value of an attribute, falling back to its default.
+    fn attribute_value(&self, attribute: &str) -> Option<&AttributeValue> {
+        let attribute = self.canonical_attribute(attribute);
+        self.attributes
+            .get(attribute)
+            .or_else(|| self.attribute_defaults.get(attribute))
+    }
+
+    /// Check that all requested attributes are known, reporting all unknown
+    /// ones at once. When missing attributes are omitted or fail the flow, or
+    /// a fixed set of attributes is returned anyway (see `map_attributes`),
+    /// any attribute may be requested. With a policy, the attributes must also
+    /// be allowed by it, whether they are known or not.
+    pub fn verify_attributes(
+        &self,
+        attributes: &[String],
+        policy: Option<&str>,
+    ) -> Result<(), Error> {
+        if let Some(policy) = policy {
+            let allowed = self
+                .attribute_policies
+                .get(policy)
+                .ok_or_else(|| Error::UnknownPolicy(policy.to_string()))?;
+            let disallowed: Vec<String> = attributes
+                .iter()
+                .filter(|attribute| !allowed.contains(*attribute))
+                .cloned()
+                .collect();
+            if !disallowed.is_empty() {
+                return Err(Error::DisallowedAttributes(policy.to_string(), disallowed));
+            }
         }
 
-        Ok(())
+        if self.omit_missing_attributes
+            || self.fail_on_missing_attributes
+            || self.override_attributes.is_some()
+        {
+            return Ok(());
+        }
+
+        let unknown = self.missing_attributes(attributes);
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::UnknownAttributes(unknown))
+        }
+    }
+
+    /// The requested attributes that have neither a value nor a default.
+    /// With a fixed set of attributes, none are missing.
+    pub fn missing_attributes(&self, attributes: &[String]) -> Vec<String> {
+        if self.override_attributes.is_some() {
+            return vec![];
+        }
+        attributes
+            .iter()
+            .filter(|attribute| self.attribute_value(attribute).is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// The result value of an attribute, with its configured transformations
+    /// applied.
+    fn result_value(&self, attribute: &str, value: &AttributeValue) -> Result<String, Error> {
+        let value = value.to_result_value(&self.generator_counter)?;
+        Ok(match self.attribute_transforms.get(attribute) {
+            Some(transforms) => transforms
+                .iter()
+                .fold(value, |value, transform| transform.apply(value)),
+            None => value,
+        })
     }
 
+    /// Look up the values of the requested attributes. Attributes without a
+    /// value or default are left out of the result when so configured, and
+    /// rejected otherwise. Aliased attributes are returned under the name
+    /// they are configured under, unless the alias is to be echoed. When
+    /// override attributes are configured, those are returned instead,
+    /// regardless of what was requested.
     pub fn map_attributes(&self, attributes: &[String]) -> Result<HashMap<String, String>, Error> {
+        if let Some(overrides) = &self.override_attributes {
+            return overrides
+                .iter()
+                .map(|(attribute, value)| {
+                    Ok((attribute.clone(), self.result_value(attribute, value)?))
+                })
+                .collect();
+        }
+
         let mut result: HashMap<String, String> = HashMap::new();
         for attribute in attributes.iter() {
-            result.insert(
-                attribute.clone(),
-                self.attributes
-                    .get(attribute)
-                    .ok_or_else(|| Error::UnknownAttribute(attribute.clone()))?
-                    .clone(),
-            );
+            let canonical = self.canonical_attribute(attribute);
+            match self.attribute_value(canonical) {
+                Some(value) => {
+                    let name = if self.echo_attribute_aliases {
+                        attribute
+                    } else {
+                        canonical
+                    };
+                    result.insert(name.to_string(), self.result_value(canonical, value)?);
+                }
+                None if self.omit_missing_attributes => {}
+                None => return Err(Error::UnknownAttribute(attribute.clone())),
+            }
         }
 
         Ok(result)
     }
 
+    /// Resolve a continuation against the configured base url, and check it
+    /// with `verify_url`. Absolute continuations are used unchanged, relative
+    /// ones must stay within the base url once resolved.
+    pub fn resolve_continuation(&self, continuation: &str) -> Result<String, Error> {
+        let resolved = match &self.continuation_base_url {
+            Some(base) => match Url::parse(continuation) {
+                Ok(_) => continuation.to_string(),
+                Err(url::ParseError::RelativeUrlWithoutBase) => {
+                    let joined = base.join(continuation)?;
+                    // Joining resolves dot segments, so a traversal shows up as
+                    // a path outside of the base path. That path ends in a
+                    // slash, so this compares whole segments.
+                    if joined.origin() != base.origin() || !joined.path().starts_with(base.path()) {
+                        return Err(Error::ContinuationOutsideBase(continuation.to_string()));
+                    }
+                    joined.to_string()
+                }
+                Err(e) => return Err(e.into()),
+            },
+            None => continuation.to_string(),
+        };
+
+        self.verify_url(&resolved, "continuation")?;
+        Ok(resolved)
+    }
+
+    /// Check a url we are going to send the user or the result to. Its host
+    /// must be on the allowlist (an empty allowlist allows all hosts), and
+    /// if so configured it must use https. Plain http is still accepted for
+    /// localhost, to keep local testing possible.
+    pub fn verify_url(&self, url: &str, name: &'static str) -> Result<(), Error> {
+        if self.allowed_continuation_hosts.is_empty() && !self.require_https {
+            return Ok(());
+        }
+
+        let parsed = Url::parse(url)?;
+        if !self.allowed_continuation_hosts.is_empty() {
+            match parsed.host_str() {
+                Some(host)
+                    if self
+                        .allowed_continuation_hosts
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(host)) => {}
+                _ => return Err(Error::DisallowedHost(url.to_string())),
+            }
+        }
+
+        if self.require_https {
+            let is_local = matches!(parsed.host_str(), Some("localhost") | Some("127.0.0.1"));
+            let secure = match parsed.scheme() {
+                "https" => true,
+                "http" => is_local,
+                _ => false,
+            };
+            if !secure {
+                return Err(Error::InsecureUrl(name, url.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn fail_on_missing_attributes(&self) -> bool {
+        self.fail_on_missing_attributes
+    }
+
+    pub fn allow_empty_attributes(&self) -> bool {
+        self.allow_empty_attributes
+    }
+
+    pub fn lenient_attribute_parsing(&self) -> bool {
+        self.lenient_attribute_parsing
+    }
+
     pub fn server_url(&self) -> &str {
         &self.server_url
     }
 
+    pub fn derive_server_url_from_host(&self) -> bool {
+        self.derive_server_url_from_host
+    }
+
     pub fn internal_url(&self) -> &str {
         &self.internal_url
     }
@@ -128,11 +1647,898 @@ impl Config {
         self.with_session
     }
 
-    pub fn encrypter(&self) -> &dyn JweEncrypter {
-        self.encrypter.as_ref()
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
+    pub fn interactive_mode(&self) -> InteractiveMode {
+        self.interactive_mode
+    }
+
+    pub fn locales(&self) -> &Locales {
+        &self.locales
+    }
+
+    pub fn allow_get_start(&self) -> bool {
+        self.allow_get_start
+    }
+
+    pub fn max_starts_per_minute(&self) -> Option<NonZeroU32> {
+        self.max_starts_per_minute
+    }
+
+    pub fn max_active_flows(&self) -> Option<usize> {
+        self.max_active_flows
+    }
+
+    /// How long a flow counts as active when it is not completed.
+    pub fn active_flow_ttl(&self) -> Duration {
+        Duration::from_secs(self.active_flow_ttl_seconds)
+    }
+
+    /// Maximum number of flows started by a single batch request.
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size
+    }
+
+    pub fn idempotency_ttl(&self) -> Duration {
+        Duration::from_secs(self.idempotency_ttl_seconds)
+    }
+
+    /// How long the payloads of short urls are kept, if short urls are
+    /// enabled.
+    pub fn short_urls_ttl(&self) -> Option<Duration> {
+        self.short_urls
+            .then(|| Duration::from_secs(self.short_url_ttl_seconds))
+    }
+
+    /// The base64 alphabet and padding of the segments of handed out urls.
+    /// Either is accepted when decoding them.
+    pub fn url_base64(&self) -> base64::Config {
+        if self.url_base64_padding {
+            base64::URL_SAFE
+        } else {
+            base64::URL_SAFE_NO_PAD
+        }
+    }
+
+    /// How long a flow waits for its second round, if step-up is enabled.
+    pub fn step_up_ttl(&self) -> Option<Duration> {
+        self.step_up
+            .then(|| Duration::from_secs(self.step_up_ttl_seconds))
+    }
+
+    /// The attributes released in the first round of a step-up flow.
+    pub fn step_up_attributes(&self) -> &HashSet<String> {
+        &self.step_up_attributes
+    }
+
+    /// How long used nonces are remembered, if replay protection is
+    /// enabled.
+    pub fn replay_protection_ttl(&self) -> Option<Duration> {
+        self.replay_protection
+            .then(|| Duration::from_secs(self.replay_protection_ttl_seconds))
+    }
+
+    pub fn max_request_body_bytes(&self) -> u64 {
+        self.max_request_body_bytes
+    }
+
+    pub fn encrypt_results(&self) -> bool {
+        self.encrypt_results
+    }
+
+    pub fn token_lifetime(&self) -> Duration {
+        Duration::from_secs(self.token_lifetime_seconds)
+    }
+
+    pub fn token_not_before_skew(&self) -> Option<Duration> {
+        self.token_not_before_skew_seconds.map(Duration::from_secs)
+    }
+
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    /// Whether tokens get the same timing claims as those produced by
+    /// `verder_helpen_jwt`.
+    pub fn default_token_timing(&self) -> bool {
+        self.token_lifetime_seconds == default_token_lifetime_seconds()
+            && self.token_not_before_skew_seconds.is_none()
+    }
+
+    pub fn session_ttl_seconds(&self) -> Option<u64> {
+        self.session_ttl_seconds
+    }
+
+    /// How long shutdown waits for attr_url posts in progress.
+    pub fn shutdown_grace_seconds(&self) -> u64 {
+        self.shutdown_grace_seconds
+    }
+
+    pub fn attr_url_concurrency(&self) -> Option<NonZeroUsize> {
+        self.attr_url_concurrency
+    }
+
+    pub fn cors(&self) -> Option<&CorsConfig> {
+        self.cors.as_ref()
+    }
+
+    pub fn security_headers(&self) -> Option<&SecurityHeadersConfig> {
+        self.security_headers.as_ref()
     }
 
-    pub fn signer(&self) -> &dyn JwsSigner {
-        self.signer.as_ref()
+    /// The level to log every request at, if enabled.
+    pub fn request_log_level(&self) -> Option<RequestLogLevel> {
+        self.log_requests.then_some(self.request_log_level)
+    }
+
+    pub fn admin_token(&self) -> Option<&str> {
+        self.admin_token.as_deref()
+    }
+
+    /// Paths of the certificate chain and private key to serve https with.
+    pub fn tls_paths(&self) -> Option<(&str, &str)> {
+        self.tls_paths
+            .as_ref()
+            .map(|(cert_path, key_path)| (cert_path.as_str(), key_path.as_str()))
+    }
+
+    pub fn log_auth_results(&self) -> bool {
+        self.log_auth_results
+    }
+
+    pub fn include_requested_attributes(&self) -> bool {
+        self.include_requested_attributes
+    }
+
+    /// A token as it may appear in the logs.
+    pub fn logged_token<'a>(&self, token: &'a str) -> &'a str {
+        if self.log_tokens {
+            token
+        } else {
+            "<redacted>"
+        }
+    }
+
+    pub fn default_status(&self) -> ResultStatus {
+        self.default_status
+    }
+
+    pub fn failed_status_behavior(&self) -> FailedStatusBehavior {
+        self.failed_status_behavior
+    }
+
+    pub fn response_delay_ms(&self) -> u64 {
+        self.response_delay_ms
+    }
+
+    pub fn redirect_status(&self) -> RedirectStatus {
+        self.redirect_status
+    }
+
+    pub fn response_delay_distribution(&self) -> DelayDistribution {
+        self.response_delay_distribution
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        self.failure_rate
+    }
+
+    pub fn random_seed(&self) -> Option<u64> {
+        self.random_seed
+    }
+
+    pub fn result_parameter_name(&self) -> &str {
+        &self.result_parameter_name
+    }
+
+    pub fn continuation_template(&self) -> bool {
+        self.continuation_template
+    }
+
+    pub fn delivery_mode(&self) -> DeliveryMode {
+        self.delivery_mode
+    }
+
+    pub fn post_field_name(&self) -> &str {
+        &self.post_field_name
+    }
+
+    pub fn post_form_method(&self) -> FormMethod {
+        self.post_form_method
+    }
+
+    pub fn attr_url_format(&self) -> AttrUrlFormat {
+        self.attr_url_format
+    }
+
+    pub fn attr_url_method(&self) -> AttrUrlMethod {
+        self.attr_url_method
+    }
+
+    pub fn jwe_serialization(&self) -> TokenSerialization {
+        self.jwe_serialization
+    }
+
+    pub fn attribute_result_shape(&self) -> AttributeResultShape {
+        self.attribute_result_shape
+    }
+
+    pub fn attr_url_expect_ack(&self) -> bool {
+        self.attr_url_expect_ack
+    }
+
+    /// Whether to show an error page for results the attr_url did not
+    /// acknowledge. Only applies when acknowledgement is expected.
+    pub fn attr_url_ack_failure_page(&self) -> bool {
+        self.attr_url_expect_ack && self.attr_url_ack_failure_page
+    }
+
+    /// Delay of the post of a result to the attr_url, if any. The delay only
+    /// applies to completed flows, not to cancellations and failures.
+    pub fn attr_url_delay(&self) -> Option<Duration> {
+        (self.attr_url_delay_ms > 0).then(|| Duration::from_millis(self.attr_url_delay_ms))
+    }
+
+    pub fn attr_url_retries(&self) -> u32 {
+        self.attr_url_retries
+    }
+
+    pub fn attr_url_retry_base_delay_ms(&self) -> u64 {
+        self.attr_url_retry_base_delay_ms
+    }
+
+    pub fn attr_url_timeout(&self) -> Option<Duration> {
+        self.attr_url_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Headers added to the posts to the attr_url, validated at startup.
+    pub fn attr_url_headers(&self) -> &HeaderMap {
+        &self.attr_url_headers
+    }
+
+    /// Content type for tokens posted to the attr_url, if overridden.
+    pub fn attr_url_content_type(&self) -> Option<&HeaderValue> {
+        self.attr_url_content_type.as_ref()
+    }
+
+    pub fn encryption_encoding(&self) -> Option<ContentEncoding> {
+        self.encryption_encoding
+    }
+
+    pub fn fault_injection(&self) -> bool {
+        self.fault_injection
+    }
+
+    pub fn test_endpoints(&self) -> bool {
+        self.test_endpoints
+    }
+
+    /// The public parts of the default keys as a JWKS document, if keys are
+    /// configured.
+    pub fn jwks(&self) -> Option<&serde_json::Value> {
+        self.jwks.as_ref()
+    }
+
+    /// The keys for the results of an audience. Without an audience, the
+    /// default keys are used.
+    pub fn tokens(&self, audience: Option<&str>) -> Result<&TokenKeys, Error> {
+        match audience {
+            Some(audience) => self
+                .audiences
+                .get(audience)
+                .ok_or_else(|| Error::UnknownAudience(audience.to_string())),
+            None => Ok(&self.tokens),
+        }
+    }
+
+    /// The signing keys being rotated, if configured.
+    pub fn key_rotation(&self) -> Option<&KeyRotation> {
+        self.key_rotation.as_ref()
+    }
+
+    pub fn inspect_keys(&self) -> Option<&InspectKeys> {
+        self.inspect_keys.as_ref()
+    }
+
+    pub fn session_update_verifier(&self) -> Option<&dyn JwsVerifier> {
+        self.session_update_verifier.as_deref()
+    }
+
+    pub fn verify_audience(&self, audience: Option<&str>) -> Result<(), Error> {
+        self.tokens(audience).map(|_| ())
+    }
+
+    /// Overview of the settings that can be changed by reloading the
+    /// configuration. Secrets and key material are left out.
+    pub fn summary(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut attributes: Vec<&String> = self.attributes.keys().collect();
+        attributes.sort();
+        let mut attribute_defaults: Vec<&String> = self.attribute_defaults.keys().collect();
+        attribute_defaults.sort();
+        let attribute_policies: BTreeMap<&String, BTreeSet<&String>> = self
+            .attribute_policies
+            .iter()
+            .map(|(name, allowed)| (name, allowed.iter().collect()))
+            .collect();
+        // Header values may hold credentials, so only show the names
+        let mut attr_url_headers: Vec<&str> = self
+            .attr_url_headers
+            .keys()
+            .map(HeaderName::as_str)
+            .collect();
+        attr_url_headers.sort_unstable();
+        let mut audiences: Vec<&String> = self.audiences.keys().collect();
+        audiences.sort();
+        let override_attributes = self.override_attributes.as_ref().map(|overrides| {
+            let mut keys: Vec<&String> = overrides.keys().collect();
+            keys.sort();
+            keys
+        });
+
+        let summary = serde_json::json!({
+            "server_url": self.server_url,
+            "internal_url": self.internal_url,
+            "attributes": attributes,
+            "attribute_defaults": attribute_defaults,
+            "override_attributes": override_attributes,
+            "attribute_transforms": self.attribute_transforms.iter().collect::<BTreeMap<_, _>>(),
+            "attribute_aliases": self.attribute_aliases.iter().collect::<BTreeMap<_, _>>(),
+            "echo_attribute_aliases": self.echo_attribute_aliases,
+            "attribute_policies": attribute_policies,
+            "omit_missing_attributes": self.omit_missing_attributes,
+            "fail_on_missing_attributes": self.fail_on_missing_attributes,
+            "allow_empty_attributes": self.allow_empty_attributes,
+            "lenient_attribute_parsing": self.lenient_attribute_parsing,
+            "with_session": self.with_session,
+            "interactive": self.interactive,
+            "interactive_mode": self.interactive_mode.as_str(),
+            "default_locale": self.locales.default_locale(),
+            "locales": self.locales.names(),
+            "allow_get_start": self.allow_get_start,
+            "max_starts_per_minute": self.max_starts_per_minute,
+            "max_active_flows": self.max_active_flows,
+            "active_flow_ttl_seconds": self.active_flow_ttl_seconds,
+            "max_batch_size": self.max_batch_size,
+            "idempotency_ttl_seconds": self.idempotency_ttl_seconds,
+            "short_urls": self.short_urls,
+            "url_base64_padding": self.url_base64_padding,
+            "short_url_ttl_seconds": self.short_url_ttl_seconds,
+            "derive_server_url_from_host": self.derive_server_url_from_host,
+            "replay_protection": self.replay_protection,
+            "replay_protection_ttl_seconds": self.replay_protection_ttl_seconds,
+            "step_up": self.step_up,
+            "step_up_attributes": self.step_up_attributes.iter().collect::<BTreeSet<_>>(),
+            "step_up_ttl_seconds": self.step_up_ttl_seconds,
+            "encrypt_results": self.encrypt_results,
+            "token_lifetime_seconds": self.token_lifetime_seconds,
+            "token_not_before_skew_seconds": self.token_not_before_skew_seconds,
+            "issuer": self.issuer,
+            "continuation_base_url": self.continuation_base_url.as_ref().map(Url::as_str),
+            "allowed_continuation_hosts": self.allowed_continuation_hosts,
+            "require_https": self.require_https,
+            "log_auth_results": self.log_auth_results,
+            "include_requested_attributes": self.include_requested_attributes,
+            "log_tokens": self.log_tokens,
+            "default_status": self.default_status.as_str(),
+            "failed_status_behavior": self.failed_status_behavior.as_str(),
+            "response_delay_ms": self.response_delay_ms,
+            "redirect_status": self.redirect_status.code(),
+            "response_delay_distribution": self.response_delay_distribution,
+            "failure_rate": self.failure_rate,
+            "result_parameter_name": self.result_parameter_name,
+            "continuation_template": self.continuation_template,
+            "delivery_mode": self.delivery_mode.as_str(),
+            "post_field_name": self.post_field_name,
+            "post_form_method": self.post_form_method.as_str(),
+            "attr_url_format": self.attr_url_format.as_str(),
+            "attr_url_method": self.attr_url_method.as_str(),
+            "jwe_serialization": self.jwe_serialization.as_str(),
+            "attribute_result_shape": self.attribute_result_shape.as_str(),
+            "attr_url_expect_ack": self.attr_url_expect_ack,
+            "attr_url_ack_failure_page": self.attr_url_ack_failure_page,
+            "attr_url_delay_ms": self.attr_url_delay_ms,
+            "attr_url_retries": self.attr_url_retries,
+            "attr_url_retry_base_delay_ms": self.attr_url_retry_base_delay_ms,
+            "attr_url_timeout_ms": self.attr_url_timeout_ms,
+            "attr_url_headers": attr_url_headers,
+            "attr_url_content_type": self
+                .attr_url_content_type
+                .as_ref()
+                .and_then(|content_type| content_type.to_str().ok()),
+            "signing_algorithm": self.signing_algorithm.map(|a| a.as_str()),
+            "encryption_algorithm": self.encryption_algorithm.map(|a| a.as_str()),
+            "encryption_encoding": self.encryption_encoding.map(|e| e.as_str()),
+            "deterministic_tokens": matches!(self.tokens, TokenKeys::Deterministic),
+            "fault_injection": self.fault_injection,
+            "test_endpoints": self.test_endpoints,
+            "audiences": audiences,
+        });
+        match summary {
+            serde_json::Value::Object(summary) => summary,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The effective configuration, for inspection by an admin. This
+    /// includes the settings that only act at launch, but secrets and key
+    /// material are replaced by a placeholder.
+    pub fn redacted(&self) -> serde_json::Value {
+        let mut view = self.summary();
+        let redacted = |value: bool| value.then_some(REDACTED);
+        let mut extra = serde_json::json!({
+            "session_ttl_seconds": self.session_ttl_seconds,
+            "shutdown_grace_seconds": self.shutdown_grace_seconds,
+            "attr_url_concurrency": self.attr_url_concurrency,
+            "random_seed": self.random_seed,
+            "max_request_body_bytes": self.max_request_body_bytes,
+            "cors": self.cors,
+            "security_headers": self.security_headers,
+            "log_requests": self.log_requests,
+            "request_log_level": self.request_log_level,
+            "tls_cert_path": self.tls_paths.as_ref().map(|(cert_path, _)| cert_path),
+            "tls_key_path": self.tls_paths.as_ref().map(|(_, key_path)| key_path),
+            "admin_token": redacted(self.admin_token.is_some()),
+            "signing_privkey": redacted(matches!(self.tokens, TokenKeys::Keys { .. })),
+            "signing_keys": self.key_rotation.as_ref().map(KeyRotation::kids),
+            "active_signing_kid": self.key_rotation.as_ref().map(KeyRotation::active_kid),
+            "encryption_pubkey": redacted(matches!(self.tokens, TokenKeys::Keys { .. })),
+            "inspect_keys": redacted(self.inspect_keys.is_some()),
+            "session_update_verify_key": redacted(self.session_update_verifier.is_some()),
+        });
+        if let Some(extra) = extra.as_object_mut() {
+            view.append(extra);
+        }
+        serde_json::Value::Object(view)
+    }
+}
+
+/// The active configuration, which can be replaced at runtime.
+pub struct SharedConfig(ArcSwap<Config>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        SharedConfig(ArcSwap::from_pointee(config))
+    }
+
+    pub fn snapshot(&self) -> Arc<Config> {
+        self.0.load_full()
+    }
+
+    /// Replace the configuration, returning the previous one.
+    pub fn replace(&self, config: Config) -> Arc<Config> {
+        self.0.swap(Arc::new(config))
+    }
+}
+
+/// The configuration as it was at the start of handling a request. Requests
+/// in flight keep using their snapshot when the configuration is replaced.
+pub struct ConfigSnapshot(Arc<Config>);
+
+impl Deref for ConfigSnapshot {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ConfigSnapshot {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        match request.rocket().state::<SharedConfig>() {
+            Some(config) => request::Outcome::Success(ConfigSnapshot(config.snapshot())),
+            None => request::Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use openssl::{
+        ec::{EcGroup, EcKey},
+        nid::Nid,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+    };
+
+    use super::*;
+
+    /// Settings all test configurations start from. Tokens are deterministic,
+    /// so tests that don't look at signatures need no keys.
+    pub(crate) const BASE_CONFIG: &str = r#"
+        server_url = "https://auth-test.example.com"
+        internal_url = "http://127.0.0.1:8000"
+        deterministic_tokens = true
+
+        [attributes]
+        email = "user@example.com"
+    "#;
+
+    /// The base configuration with each of the layers merged over it, in
+    /// order. Top-level settings can't follow a table in TOML, so these go
+    /// in a layer of their own.
+    pub(crate) fn figment(layers: &[&str]) -> Figment {
+        layers.iter().fold(
+            Figment::from(Toml::string(BASE_CONFIG)),
+            |figment, layer| figment.merge(Toml::string(layer)),
+        )
+    }
+
+    pub(crate) fn config(layers: &[&str]) -> Config {
+        figment(layers)
+            .extract()
+            .unwrap_or_else(|e| panic!("Invalid test configuration: {}", e))
+    }
+
+    /// A freshly generated key pair, PEM encoded.
+    pub(crate) struct KeyPair {
+        pub(crate) kind: &'static str,
+        pub(crate) private: String,
+        pub(crate) public: String,
+    }
+
+    impl KeyPair {
+        pub(crate) fn rsa() -> KeyPair {
+            let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+            KeyPair::from_key("RSA", key)
+        }
+
+        /// A P-256 key pair.
+        pub(crate) fn ec() -> KeyPair {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+            KeyPair::from_key("EC", key)
+        }
+
+        fn from_key(kind: &'static str, key: PKey<Private>) -> KeyPair {
+            KeyPair {
+                kind,
+                private: String::from_utf8(key.private_key_to_pem_pkcs8().unwrap()).unwrap(),
+                public: String::from_utf8(key.public_key_to_pem().unwrap()).unwrap(),
+            }
+        }
+
+        /// A key table, like `[signing_privkey]`, with one half of the pair.
+        pub(crate) fn table(&self, name: &str, private: bool) -> String {
+            let key = if private { &self.private } else { &self.public };
+            format!(
+                "[{}]\ntype = \"{}\"\nkey = \"\"\"\n{}\"\"\"\n",
+                name, self.kind, key
+            )
+        }
+    }
+
+    /// Real keys for results of the default audience.
+    pub(crate) struct TestKeys {
+        pub(crate) signing: KeyPair,
+        pub(crate) encryption: KeyPair,
+    }
+
+    impl TestKeys {
+        pub(crate) fn rsa() -> TestKeys {
+            TestKeys {
+                signing: KeyPair::rsa(),
+                encryption: KeyPair::rsa(),
+            }
+        }
+
+        /// The configuration layer with these keys, and their counterparts
+        /// as the inspect keys. The algorithms are set explicitly, so tests
+        /// know how to verify and decrypt the tokens.
+        pub(crate) fn config(&self) -> String {
+            [
+                format!("deterministic_tokens = false\n{}", self.algorithms()),
+                self.signing.table("signing_privkey", true),
+                self.encryption.table("encryption_pubkey", false),
+                self.signing.table("inspect_keys.signing_pubkey", false),
+                self.encryption
+                    .table("inspect_keys.encryption_privkey", true),
+            ]
+            .concat()
+        }
+
+        /// The configuration layer with these keys as those of an audience.
+        pub(crate) fn audience(&self, audience: &str) -> String {
+            [
+                format!("[audiences.{}]\n{}", audience, self.algorithms()),
+                self.signing
+                    .table(&format!("audiences.{}.signing_privkey", audience), true),
+                self.encryption
+                    .table(&format!("audiences.{}.encryption_pubkey", audience), false),
+            ]
+            .concat()
+        }
+
+        fn algorithms(&self) -> String {
+            let signing_algorithm = match self.signing.kind {
+                "EC" => "ES256",
+                _ => "RS256",
+            };
+            format!(
+                "signing_algorithm = \"{}\"\nencryption_algorithm = \"RSA-OAEP\"\n",
+                signing_algorithm
+            )
+        }
+    }
+
+    fn attributes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn maps_single_and_multiple_values() {
+        let config = config(&[r#"
+            [attributes]
+            roles = ["user", "tester"]
+        "#]);
+
+        let values = config
+            .map_attributes(&attributes(&["email", "roles"]))
+            .unwrap();
+        assert_eq!(values["email"], "user@example.com");
+        let roles: Vec<String> = serde_json::from_str(&values["roles"]).unwrap();
+        assert_eq!(roles, ["user", "tester"]);
+    }
+
+    #[test]
+    fn reports_all_unknown_attributes() {
+        let config = config(&[]);
+
+        assert!(config
+            .verify_attributes(&attributes(&["email"]), None)
+            .is_ok());
+        match config.verify_attributes(&attributes(&["name", "email", "phone"]), None) {
+            Err(Error::UnknownAttributes(unknown)) => assert_eq!(unknown, ["name", "phone"]),
+            other => panic!("Expected unknown attributes, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_algorithms_not_fitting_the_key() {
+        let keys = TestKeys::rsa();
+
+        assert!(figment(&[&keys.config()]).extract::<Config>().is_ok());
+        assert!(figment(&[&keys.config(), r#"signing_algorithm = "ES256""#])
+            .extract::<Config>()
+            .is_err());
+        assert!(
+            figment(&[&keys.config(), r#"encryption_algorithm = "ECDH-ES""#])
+                .extract::<Config>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_a_signing_algorithm_for_another_kind_of_key() {
+        for (keys, algorithm, kind) in [
+            (TestKeys::rsa(), "ES256", "RSA"),
+            (
+                TestKeys {
+                    signing: KeyPair::ec(),
+                    encryption: KeyPair::rsa(),
+                },
+                "RS256",
+                "EC",
+            ),
+        ] {
+            let layer = format!("signing_algorithm = \"{}\"", algorithm);
+            let raw: RawConfig = figment(&[&keys.config(), &layer]).extract().unwrap();
+            assert!(matches!(
+                Config::try_from(raw),
+                Err(Error::UnsupportedAlgorithm(rejected, key)) if rejected == algorithm && key == kind
+            ));
+        }
+    }
+
+    #[test]
+    fn fills_in_defaults_and_omits_missing_attributes() {
+        let defaults = r#"
+            [attribute_defaults]
+            email = "default@example.com"
+            name = "Default Name"
+        "#;
+        let requested = attributes(&["email", "name", "phone"]);
+
+        let strict = config(&[defaults]);
+        assert!(matches!(
+            strict.map_attributes(&requested),
+            Err(Error::UnknownAttribute(attribute)) if attribute == "phone"
+        ));
+
+        let lenient = config(&["omit_missing_attributes = true", defaults]);
+        let values = lenient.map_attributes(&requested).unwrap();
+        assert_eq!(values["email"], "user@example.com");
+        assert_eq!(values["name"], "Default Name");
+        assert!(!values.contains_key("phone"));
+    }
+
+    #[test]
+    fn rejects_a_zero_start_rate() {
+        assert!(figment(&["max_starts_per_minute = 2"])
+            .extract::<Config>()
+            .is_ok());
+        assert!(figment(&["max_starts_per_minute = 0"])
+            .extract::<Config>()
+            .is_err());
+    }
+
+    #[test]
+    fn resolves_continuations_within_the_base_path() {
+        let config = config(&[r#"continuation_base_url = "https://rp.example.com/app""#]);
+        let resolve = |continuation| config.resolve_continuation(continuation);
+
+        assert_eq!(
+            resolve("https://other.example.com/done").unwrap(),
+            "https://other.example.com/done"
+        );
+        assert_eq!(resolve("done").unwrap(), "https://rp.example.com/app/done");
+        assert_eq!(
+            resolve("/app/done").unwrap(),
+            "https://rp.example.com/app/done"
+        );
+        for outside in ["../admin", "/application", "/admin"] {
+            assert!(
+                matches!(resolve(outside), Err(Error::ContinuationOutsideBase(_))),
+                "{}",
+                outside
+            );
+        }
+    }
+
+    #[test]
+    fn returns_the_override_attributes_whatever_is_requested() {
+        let config = config(&[r#"
+            [override_attributes]
+            name = "Fixed Name"
+        "#]);
+        let requested = attributes(&["email", "phone"]);
+
+        assert!(config.verify_attributes(&requested, None).is_ok());
+        let values = config.map_attributes(&requested).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values["name"], "Fixed Name");
+    }
+
+    #[test]
+    fn transforms_attribute_values_in_order() {
+        let apply = |transform: Transform| transform.apply("User@Example.com".to_string());
+        assert_eq!(apply(Transform::Identity), "User@Example.com");
+        assert_eq!(apply(Transform::Upper), "USER@EXAMPLE.COM");
+        assert_eq!(apply(Transform::Lower), "user@example.com");
+        assert_eq!(
+            apply(Transform::Prefix("mailto:".to_string())),
+            "mailto:User@Example.com"
+        );
+
+        let config = config(&[r#"
+            [attributes]
+            code = "ABC"
+
+            [attribute_transforms]
+            code = ["lower", "sha256-hex"]
+        "#]);
+        let values = config.map_attributes(&attributes(&["code"])).unwrap();
+        assert_eq!(
+            values["code"],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn checks_attributes_against_the_policy() {
+        let config = config(&[r#"
+            [attributes]
+            phone = "0612345678"
+
+            [attribute_policies]
+            basic = ["email"]
+            full = ["email", "phone"]
+        "#]);
+        let requested = attributes(&["email", "phone"]);
+
+        assert!(config.verify_attributes(&requested, Some("full")).is_ok());
+        match config.verify_attributes(&requested, Some("basic")) {
+            Err(Error::DisallowedAttributes(policy, disallowed)) => {
+                assert_eq!(policy, "basic");
+                assert_eq!(disallowed, ["phone"]);
+            }
+            other => panic!("Expected disallowed attributes, got {:?}", other),
+        }
+        assert!(matches!(
+            config.verify_attributes(&requested, Some("other")),
+            Err(Error::UnknownPolicy(policy)) if policy == "other"
+        ));
+    }
+
+    #[test]
+    fn maps_aliases_to_their_attribute() {
+        let aliases = r#"
+            [attribute_aliases]
+            mail = "email"
+        "#;
+        let requested = attributes(&["mail"]);
+
+        let canonical = config(&[aliases]);
+        assert!(canonical.verify_attributes(&requested, None).is_ok());
+        let values = canonical.map_attributes(&requested).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values["email"], "user@example.com");
+
+        let echoing = config(&["echo_attribute_aliases = true", aliases]);
+        let values = echoing.map_attributes(&requested).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values["mail"], "user@example.com");
+    }
+
+    #[test]
+    fn rejects_a_failure_rate_outside_zero_and_one() {
+        for rate in ["0.0", "0.5", "1.0"] {
+            let layer = format!("failure_rate = {}", rate);
+            assert!(figment(&[&layer]).extract::<Config>().is_ok(), "{}", rate);
+        }
+        for rate in ["-0.1", "1.5"] {
+            let layer = format!("failure_rate = {}", rate);
+            assert!(figment(&[&layer]).extract::<Config>().is_err(), "{}", rate);
+        }
+    }
+
+    #[test]
+    fn generates_values_for_every_flow() {
+        let config = config(&[r#"
+            [attributes]
+            id = { generator = "uuid" }
+            number = { generator = "counter" }
+        "#]);
+        let requested = attributes(&["email", "id", "number"]);
+
+        let first = config.map_attributes(&requested).unwrap();
+        let second = config.map_attributes(&requested).unwrap();
+        assert_eq!(first["email"], second["email"]);
+        assert_ne!(first["id"], second["id"]);
+        assert!(uuid::Uuid::parse_str(&first["id"]).is_ok());
+        assert_eq!(first["number"], "1");
+        assert_eq!(second["number"], "2");
+    }
+
+    #[test]
+    fn only_shows_the_ack_failure_page_without_concurrency() {
+        let ack = "attr_url_expect_ack = true\nattr_url_ack_failure_page = true";
+        assert!(figment(&[ack]).extract::<Config>().is_ok());
+        assert!(
+            figment(&["attr_url_concurrency = 4\nattr_url_expect_ack = true"])
+                .extract::<Config>()
+                .is_ok()
+        );
+        let layer = format!("{}\nattr_url_concurrency = 4", ack);
+        assert!(figment(&[&layer]).extract::<Config>().is_err());
+    }
+
+    #[test]
+    fn only_includes_requested_attributes_without_https() {
+        assert!(figment(&["include_requested_attributes = true"])
+            .extract::<Config>()
+            .is_ok());
+        assert!(
+            figment(&["include_requested_attributes = true\nrequire_https = true"])
+                .extract::<Config>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn only_accepts_redirect_statuses_keeping_the_flow() {
+        for code in [302, 303, 307] {
+            let layer = format!("redirect_status = {}", code);
+            let config = config(&[&layer]);
+            assert_eq!(config.redirect_status().code(), code);
+        }
+        for code in [200, 301, 308] {
+            let layer = format!("redirect_status = {}", code);
+            assert!(figment(&[&layer]).extract::<Config>().is_err(), "{}", code);
+        }
     }
 }
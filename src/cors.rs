@@ -0,0 +1,93 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    options, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+
+/// Routes the CORS headers are added to. These are the routes meant to be
+/// called from scripts, the browser flow itself is navigated to and needs no
+/// CORS.
+const CORS_ROUTES: &[&str] = &["/start_authentication", "/session/"];
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CorsConfig {
+    allowed_origins: Vec<String>,
+    #[serde(default = "default_allowed_methods")]
+    allowed_methods: Vec<String>,
+    #[serde(default = "default_allowed_headers")]
+    allowed_headers: Vec<String>,
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    vec!["GET".to_string(), "POST".to_string(), "OPTIONS".to_string()]
+}
+
+fn default_allowed_headers() -> Vec<String> {
+    vec!["Content-Type".to_string()]
+}
+
+pub struct Cors {
+    config: CorsConfig,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Cors {
+        Cors { config }
+    }
+
+    fn allowed_origin(&self, origin: Option<&str>) -> Option<String> {
+        if self.config.allowed_origins.iter().any(|o| o == "*") {
+            return Some("*".to_string());
+        }
+        let origin = origin?;
+        self.config
+            .allowed_origins
+            .iter()
+            .find(|allowed| allowed.as_str() == origin)
+            .cloned()
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let path = request.uri().path();
+        if !CORS_ROUTES.iter().any(|route| path.starts_with(route)) {
+            return;
+        }
+
+        if let Some(origin) = self.allowed_origin(request.headers().get_one("Origin")) {
+            if origin != "*" {
+                response.set_header(Header::new("Vary", "Origin"));
+            }
+            response.set_header(Header::new("Access-Control-Allow-Origin", origin));
+            response.set_header(Header::new(
+                "Access-Control-Allow-Methods",
+                self.config.allowed_methods.join(", "),
+            ));
+            response.set_header(Header::new(
+                "Access-Control-Allow-Headers",
+                self.config.allowed_headers.join(", "),
+            ));
+        }
+    }
+}
+
+/// Answer CORS preflight requests. The headers themselves are added by the
+/// fairing.
+#[options("/start_authentication")]
+pub async fn preflight_start() {}
+
+#[options("/start_authentication/batch")]
+pub async fn preflight_start_batch() {}
+
+#[options("/session/<_..>")]
+pub async fn preflight_session() {}
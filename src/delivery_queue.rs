@@ -0,0 +1,28 @@
+use std::{num::NonZeroUsize, sync::Arc};
+
+use rocket::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Bound on the number of attr_url deliveries in flight, so a burst of flows
+/// doesn't overwhelm a relying party. Deliveries beyond the bound wait for a
+/// slot in the order they arrived. Without a bound, deliveries never wait.
+#[derive(Debug, Default, Clone)]
+pub struct DeliveryQueue(Option<Arc<Semaphore>>);
+
+impl DeliveryQueue {
+    pub fn new(concurrency: Option<NonZeroUsize>) -> DeliveryQueue {
+        DeliveryQueue(concurrency.map(|concurrency| Arc::new(Semaphore::new(concurrency.get()))))
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Wait for a slot, which is taken for as long as the permit is held.
+    pub async fn enter(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.0 {
+            // The semaphore is never closed
+            Some(slots) => slots.clone().acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+}
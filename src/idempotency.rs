@@ -0,0 +1,90 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use rocket::request::{self, FromRequest, Request};
+use serde_json::Value;
+
+/// Value of the `Idempotency-Key` header of a request, if it has one.
+pub struct IdempotencyKey(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Infallible> {
+        request::Outcome::Success(IdempotencyKey(
+            request
+                .headers()
+                .get_one("Idempotency-Key")
+                .map(str::to_string),
+        ))
+    }
+}
+
+/// An idempotency key was used again for a different request.
+#[derive(Debug)]
+pub struct Conflict;
+
+struct Entry {
+    fingerprint: String,
+    response: Value,
+    expires: Instant,
+}
+
+/// Responses to start requests with an idempotency key, so a retried request
+/// gets the flow of the original one instead of a new flow. Responses are
+/// forgotten after a while, after which a key can be used again.
+#[derive(Default)]
+pub struct IdempotencyStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyStore {
+    /// The response stored for a key, or the response produced by `start`,
+    /// which is stored if it succeeds. The fingerprint identifies the
+    /// request, reusing a key for a different one is a conflict. The store
+    /// stays locked while starting, so concurrent retries can't both start
+    /// a flow.
+    pub fn get_or_start<E: From<Conflict>>(
+        &self,
+        key: &str,
+        fingerprint: String,
+        ttl: Duration,
+        start: impl FnOnce() -> Result<Value, E>,
+    ) -> Result<Value, E> {
+        let now = Instant::now();
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.retain(|_, entry| entry.expires > now);
+        if let Some(entry) = entries.get(key) {
+            return if entry.fingerprint == fingerprint {
+                Ok(entry.response.clone())
+            } else {
+                Err(Conflict.into())
+            };
+        }
+
+        let response = start()?;
+        entries.insert(
+            key.to_string(),
+            Entry {
+                fingerprint,
+                response: response.clone(),
+                expires: now + ttl,
+            },
+        );
+        Ok(response)
+    }
+
+    /// Forget all stored responses, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+}
@@ -0,0 +1,116 @@
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    nid::Nid,
+    pkey::{PKey, Public},
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use verder_helpen_jwt::{EncryptionKeyConfig, SignKeyConfig};
+
+use crate::{
+    config::Error,
+    keys::{EncryptionAlgorithm, SigningAlgorithm},
+};
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// The public key for a PEM encoded key. Signing keys are private keys, their
+/// public part is derived from them.
+fn public_key(pem: &str) -> Result<PKey<Public>, Error> {
+    match PKey::private_key_from_pem(pem.as_bytes()) {
+        Ok(private) => Ok(PKey::public_key_from_pem(&private.public_key_to_pem()?)?),
+        Err(_) => Ok(PKey::public_key_from_pem(pem.as_bytes())?),
+    }
+}
+
+/// The public members of the JWK for a key, with its RFC 7638 thumbprint,
+/// which serves as its key id.
+fn public_members(pem: &str) -> Result<(Value, String), Error> {
+    let key = public_key(pem)?;
+
+    if let Ok(rsa) = key.rsa() {
+        let e = encode(&rsa.e().to_vec());
+        let n = encode(&rsa.n().to_vec());
+        // The thumbprint covers the required members, in lexicographic order
+        let thumbprint = format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, e, n);
+        return Ok((
+            json!({ "kty": "RSA", "n": n, "e": e }),
+            encode(&Sha256::digest(thumbprint.as_bytes())),
+        ));
+    }
+
+    let ec = key.ec_key()?;
+    let group = ec.group();
+    let crv = match group.curve_name() {
+        Some(Nid::X9_62_PRIME256V1) => "P-256",
+        Some(Nid::SECP384R1) => "P-384",
+        Some(Nid::SECP521R1) => "P-521",
+        Some(Nid::SECP256K1) => "secp256k1",
+        _ => return Err(Error::UnsupportedCurve),
+    };
+    let mut x = BigNum::new()?;
+    let mut y = BigNum::new()?;
+    let mut context = BigNumContext::new()?;
+    ec.public_key()
+        .affine_coordinates(group, &mut x, &mut y, &mut context)?;
+    // Coordinates are padded to the size of the curve
+    let size = ((group.degree() + 7) / 8) as i32;
+    let x = encode(&x.to_vec_padded(size)?);
+    let y = encode(&y.to_vec_padded(size)?);
+    let thumbprint = format!(r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#, crv, x, y);
+    Ok((
+        json!({ "kty": "EC", "crv": crv, "x": x, "y": y }),
+        encode(&Sha256::digest(thumbprint.as_bytes())),
+    ))
+}
+
+fn jwk(pem: &str, usage: &str, algorithm: Option<&str>) -> Result<Value, Error> {
+    let (mut jwk, kid) = public_members(pem)?;
+    jwk["use"] = json!(usage);
+    jwk["kid"] = json!(kid);
+    if let Some(algorithm) = algorithm {
+        jwk["alg"] = json!(algorithm);
+    }
+    Ok(jwk)
+}
+
+/// Public JWK of a signing key with a configured kid, for keys that are
+/// rotated, whose tokens carry that kid.
+pub fn named_jwk(
+    key: &SignKeyConfig,
+    kid: &str,
+    algorithm: Option<SigningAlgorithm>,
+) -> Result<Value, Error> {
+    let pem = match key {
+        SignKeyConfig::RSA { key } | SignKeyConfig::EC { key } => key,
+    };
+    let mut jwk = jwk(pem, "sig", algorithm.map(|a| a.as_str()))?;
+    jwk["kid"] = json!(kid);
+    Ok(jwk)
+}
+
+/// JWKS document with the public parts of the default signing and
+/// encryption keys. The algorithms are only included when configured
+/// explicitly.
+pub fn public_jwks(
+    signing_privkey: &SignKeyConfig,
+    signing_algorithm: Option<SigningAlgorithm>,
+    encryption_pubkey: &EncryptionKeyConfig,
+    encryption_algorithm: Option<EncryptionAlgorithm>,
+) -> Result<Value, Error> {
+    let signing_pem = match signing_privkey {
+        SignKeyConfig::RSA { key } | SignKeyConfig::EC { key } => key,
+    };
+    let encryption_pem = match encryption_pubkey {
+        EncryptionKeyConfig::RSA { key } | EncryptionKeyConfig::EC { key } => key,
+    };
+
+    Ok(json!({
+        "keys": [
+            jwk(signing_pem, "sig", signing_algorithm.map(|a| a.as_str()))?,
+            jwk(encryption_pem, "enc", encryption_algorithm.map(|a| a.as_str()))?,
+        ]
+    }))
+}
@@ -0,0 +1,285 @@
+use std::convert::TryFrom;
+
+use josekit::{
+    jwe::{self, JweDecrypter, JweEncrypter},
+    jws::{self, JwsSigner, JwsVerifier},
+};
+use serde::Deserialize;
+use verder_helpen_jwt::{EncryptionKeyConfig, SignKeyConfig};
+
+use crate::config::Error;
+
+/// Algorithm used to sign results. When not configured, the default for the
+/// type of the signing key is used.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigningAlgorithm {
+    #[serde(rename = "RS256")]
+    Rs256,
+    #[serde(rename = "RS384")]
+    Rs384,
+    #[serde(rename = "RS512")]
+    Rs512,
+    #[serde(rename = "PS256")]
+    Ps256,
+    #[serde(rename = "PS384")]
+    Ps384,
+    #[serde(rename = "PS512")]
+    Ps512,
+    #[serde(rename = "ES256")]
+    Es256,
+    #[serde(rename = "ES384")]
+    Es384,
+    #[serde(rename = "ES512")]
+    Es512,
+}
+
+impl SigningAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SigningAlgorithm::Rs256 => "RS256",
+            SigningAlgorithm::Rs384 => "RS384",
+            SigningAlgorithm::Rs512 => "RS512",
+            SigningAlgorithm::Ps256 => "PS256",
+            SigningAlgorithm::Ps384 => "PS384",
+            SigningAlgorithm::Ps512 => "PS512",
+            SigningAlgorithm::Es256 => "ES256",
+            SigningAlgorithm::Es384 => "ES384",
+            SigningAlgorithm::Es512 => "ES512",
+        }
+    }
+}
+
+/// Algorithm used to encrypt the content encryption key of results. When not
+/// configured, the default for the type of the encryption key is used.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionAlgorithm {
+    #[serde(rename = "RSA-OAEP")]
+    RsaOaep,
+    #[serde(rename = "RSA-OAEP-256")]
+    RsaOaep256,
+    #[serde(rename = "ECDH-ES")]
+    EcdhEs,
+    #[serde(rename = "ECDH-ES+A128KW")]
+    EcdhEsA128Kw,
+    #[serde(rename = "ECDH-ES+A256KW")]
+    EcdhEsA256Kw,
+}
+
+impl EncryptionAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EncryptionAlgorithm::RsaOaep => "RSA-OAEP",
+            EncryptionAlgorithm::RsaOaep256 => "RSA-OAEP-256",
+            EncryptionAlgorithm::EcdhEs => "ECDH-ES",
+            EncryptionAlgorithm::EcdhEsA128Kw => "ECDH-ES+A128KW",
+            EncryptionAlgorithm::EcdhEsA256Kw => "ECDH-ES+A256KW",
+        }
+    }
+}
+
+/// Content encryption of results. When not configured, the encoding chosen by
+/// `verder_helpen_jwt` is used.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    #[serde(rename = "A128CBC-HS256")]
+    A128CbcHs256,
+    #[serde(rename = "A192CBC-HS384")]
+    A192CbcHs384,
+    #[serde(rename = "A256CBC-HS512")]
+    A256CbcHs512,
+    #[serde(rename = "A128GCM")]
+    A128Gcm,
+    #[serde(rename = "A192GCM")]
+    A192Gcm,
+    #[serde(rename = "A256GCM")]
+    A256Gcm,
+}
+
+impl ContentEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::A128CbcHs256 => "A128CBC-HS256",
+            ContentEncoding::A192CbcHs384 => "A192CBC-HS384",
+            ContentEncoding::A256CbcHs512 => "A256CBC-HS512",
+            ContentEncoding::A128Gcm => "A128GCM",
+            ContentEncoding::A192Gcm => "A192GCM",
+            ContentEncoding::A256Gcm => "A256GCM",
+        }
+    }
+}
+
+/// Construct the signer for the configured key, using the given algorithm if
+/// any. Algorithms that don't fit the type of key are rejected.
+pub fn signer(
+    key: SignKeyConfig,
+    algorithm: Option<SigningAlgorithm>,
+) -> Result<Box<dyn JwsSigner>, Error> {
+    let algorithm = match algorithm {
+        Some(algorithm) => algorithm,
+        None => return Ok(Box::<dyn JwsSigner>::try_from(key)?),
+    };
+
+    let signer: Box<dyn JwsSigner> = match (key, algorithm) {
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Rs256) => {
+            Box::new(jws::RS256.signer_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Rs384) => {
+            Box::new(jws::RS384.signer_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Rs512) => {
+            Box::new(jws::RS512.signer_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Ps256) => {
+            Box::new(jws::PS256.signer_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Ps384) => {
+            Box::new(jws::PS384.signer_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Ps512) => {
+            Box::new(jws::PS512.signer_from_pem(key)?)
+        }
+        (SignKeyConfig::EC { key }, SigningAlgorithm::Es256) => {
+            Box::new(jws::ES256.signer_from_pem(key)?)
+        }
+        (SignKeyConfig::EC { key }, SigningAlgorithm::Es384) => {
+            Box::new(jws::ES384.signer_from_pem(key)?)
+        }
+        (SignKeyConfig::EC { key }, SigningAlgorithm::Es512) => {
+            Box::new(jws::ES512.signer_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { .. }, algorithm) => {
+            return Err(Error::UnsupportedAlgorithm(algorithm.as_str(), "RSA"))
+        }
+        (SignKeyConfig::EC { .. }, algorithm) => {
+            return Err(Error::UnsupportedAlgorithm(algorithm.as_str(), "EC"))
+        }
+    };
+
+    Ok(signer)
+}
+
+/// Construct the encrypter for the configured key, using the given algorithm
+/// if any. Algorithms that don't fit the type of key are rejected.
+pub fn encrypter(
+    key: EncryptionKeyConfig,
+    algorithm: Option<EncryptionAlgorithm>,
+) -> Result<Box<dyn JweEncrypter>, Error> {
+    let algorithm = match algorithm {
+        Some(algorithm) => algorithm,
+        None => return Ok(Box::<dyn JweEncrypter>::try_from(key)?),
+    };
+
+    let encrypter: Box<dyn JweEncrypter> = match (key, algorithm) {
+        (EncryptionKeyConfig::RSA { key }, EncryptionAlgorithm::RsaOaep) => {
+            Box::new(jwe::RSA_OAEP.encrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::RSA { key }, EncryptionAlgorithm::RsaOaep256) => {
+            Box::new(jwe::RSA_OAEP_256.encrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::EC { key }, EncryptionAlgorithm::EcdhEs) => {
+            Box::new(jwe::ECDH_ES.encrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::EC { key }, EncryptionAlgorithm::EcdhEsA128Kw) => {
+            Box::new(jwe::ECDH_ES_A128KW.encrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::EC { key }, EncryptionAlgorithm::EcdhEsA256Kw) => {
+            Box::new(jwe::ECDH_ES_A256KW.encrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::RSA { .. }, algorithm) => {
+            return Err(Error::UnsupportedAlgorithm(algorithm.as_str(), "RSA"))
+        }
+        (EncryptionKeyConfig::EC { .. }, algorithm) => {
+            return Err(Error::UnsupportedAlgorithm(algorithm.as_str(), "EC"))
+        }
+    };
+
+    Ok(encrypter)
+}
+
+/// Construct the verifier for a public signing key, the counterpart of
+/// `signer`. Used to inspect the results we produce.
+pub fn verifier(
+    key: SignKeyConfig,
+    algorithm: Option<SigningAlgorithm>,
+) -> Result<Box<dyn JwsVerifier>, Error> {
+    let algorithm = match algorithm {
+        Some(algorithm) => algorithm,
+        None => return Ok(Box::<dyn JwsVerifier>::try_from(key)?),
+    };
+
+    let verifier: Box<dyn JwsVerifier> = match (key, algorithm) {
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Rs256) => {
+            Box::new(jws::RS256.verifier_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Rs384) => {
+            Box::new(jws::RS384.verifier_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Rs512) => {
+            Box::new(jws::RS512.verifier_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Ps256) => {
+            Box::new(jws::PS256.verifier_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Ps384) => {
+            Box::new(jws::PS384.verifier_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { key }, SigningAlgorithm::Ps512) => {
+            Box::new(jws::PS512.verifier_from_pem(key)?)
+        }
+        (SignKeyConfig::EC { key }, SigningAlgorithm::Es256) => {
+            Box::new(jws::ES256.verifier_from_pem(key)?)
+        }
+        (SignKeyConfig::EC { key }, SigningAlgorithm::Es384) => {
+            Box::new(jws::ES384.verifier_from_pem(key)?)
+        }
+        (SignKeyConfig::EC { key }, SigningAlgorithm::Es512) => {
+            Box::new(jws::ES512.verifier_from_pem(key)?)
+        }
+        (SignKeyConfig::RSA { .. }, algorithm) => {
+            return Err(Error::UnsupportedAlgorithm(algorithm.as_str(), "RSA"))
+        }
+        (SignKeyConfig::EC { .. }, algorithm) => {
+            return Err(Error::UnsupportedAlgorithm(algorithm.as_str(), "EC"))
+        }
+    };
+
+    Ok(verifier)
+}
+
+/// Construct the decrypter for a private encryption key, the counterpart of
+/// `encrypter`. Used to inspect the results we produce.
+pub fn decrypter(
+    key: EncryptionKeyConfig,
+    algorithm: Option<EncryptionAlgorithm>,
+) -> Result<Box<dyn JweDecrypter>, Error> {
+    let algorithm = match algorithm {
+        Some(algorithm) => algorithm,
+        None => return Ok(Box::<dyn JweDecrypter>::try_from(key)?),
+    };
+
+    let decrypter: Box<dyn JweDecrypter> = match (key, algorithm) {
+        (EncryptionKeyConfig::RSA { key }, EncryptionAlgorithm::RsaOaep) => {
+            Box::new(jwe::RSA_OAEP.decrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::RSA { key }, EncryptionAlgorithm::RsaOaep256) => {
+            Box::new(jwe::RSA_OAEP_256.decrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::EC { key }, EncryptionAlgorithm::EcdhEs) => {
+            Box::new(jwe::ECDH_ES.decrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::EC { key }, EncryptionAlgorithm::EcdhEsA128Kw) => {
+            Box::new(jwe::ECDH_ES_A128KW.decrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::EC { key }, EncryptionAlgorithm::EcdhEsA256Kw) => {
+            Box::new(jwe::ECDH_ES_A256KW.decrypter_from_pem(key)?)
+        }
+        (EncryptionKeyConfig::RSA { .. }, algorithm) => {
+            return Err(Error::UnsupportedAlgorithm(algorithm.as_str(), "RSA"))
+        }
+        (EncryptionKeyConfig::EC { .. }, algorithm) => {
+            return Err(Error::UnsupportedAlgorithm(algorithm.as_str(), "EC"))
+        }
+    };
+
+    Ok(decrypter)
+}
@@ -0,0 +1,3327 @@
+//! Authentication plugin for testing the Verder Helpen platform, which
+//! completes flows with preconfigured attributes instead of authenticating.
+//!
+//! The binary in this crate serves it with the configuration Rocket loads,
+//! from `Rocket.toml` or the file named by `ROCKET_CONFIG`. Other crates can
+//! use [`build_rocket`] to run it in-process, for instance with
+//! `rocket::local::asynchronous::Client` in black-box tests of the broker.
+
+// The configuration summary is a single `json!` object, which expands a few
+// macro levels deep per entry
+#![recursion_limit = "256"]
+
+use std::{
+    collections::HashMap, error::Error as StdError, fmt::Display, sync::Arc, time::Duration,
+};
+
+use active_flows::ActiveFlows;
+use admin::ConfigSource;
+use askama::Template;
+use attr_sink::AttrSink;
+use base64::URL_SAFE_NO_PAD;
+use chaos::Chaos;
+pub use config::Config;
+use config::{
+    AttrUrlFormat, AttributeResultShape, ConfigSnapshot, DeliveryMode, FailedStatusBehavior,
+    InteractiveMode, ResultStatus, SharedConfig, TokenSerialization,
+};
+use cors::Cors;
+use delivery_queue::DeliveryQueue;
+use idempotency::{IdempotencyKey, IdempotencyStore};
+use locale::{AcceptLanguage, Messages};
+use metrics::Metrics;
+use nonce::NonceStore;
+use payload::{PayloadStore, STORED_PREFIX};
+use rate_limit::StartLimiter;
+use request_log::RequestLog;
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use rocket::{
+    catch, catchers,
+    fairing::AdHoc,
+    figment::{self, Figment},
+    form::{Form, FromForm},
+    futures::future::join_all,
+    get,
+    http::Status,
+    post,
+    request::{self, FromRequest, Request},
+    response::{
+        content::{RawHtml, RawJson},
+        status::Custom,
+        Redirect,
+    },
+    routes,
+    serde::json::Json,
+    Build, Responder, Rocket, State,
+};
+use security_headers::SecurityHeaders;
+use serde::{Deserialize, Serialize};
+use server_url::ServerUrl;
+use session::{
+    Activity, ActivityRecord, SessionStatus, SessionStore, SessionUpdateResult, SignedUpdate,
+};
+use shutdown::PendingPosts;
+use step_up::{Step, StepUpStore};
+use token::Corruption;
+use tracing::{debug, error, info, warn, Instrument};
+use url::Url;
+use uuid::Uuid;
+use verder_helpen_proto::{AuthResult, AuthStatus, StartAuthRequest, StartAuthResponse};
+
+mod active_flows;
+mod admin;
+mod attr_sink;
+mod chaos;
+mod config;
+mod cors;
+mod delivery_queue;
+mod idempotency;
+mod jwks;
+mod keys;
+mod locale;
+mod metrics;
+mod nonce;
+mod payload;
+mod rate_limit;
+mod request_log;
+mod security_headers;
+mod server_url;
+mod session;
+mod shutdown;
+mod step_up;
+mod token;
+
+#[derive(Debug)]
+enum Error {
+    Config(config::Error),
+    Decode(base64::DecodeError),
+    Template(askama::Error),
+    Json(serde_json::Error),
+    Utf(std::str::Utf8Error),
+    Jwt(verder_helpen_jwt::Error),
+    Jose(josekit::JoseError),
+    Metrics(prometheus::Error),
+    RateLimited(Duration),
+    TooManyActiveFlows(usize),
+    InvalidToken(String),
+    UnknownPayload(String),
+    Replayed,
+    IdempotencyConflict,
+    BatchTooLarge(usize, usize),
+    BatchEntry(usize, Box<Error>),
+    Segment(&'static str, Box<Error>),
+}
+
+impl Error {
+    fn status(&self) -> Status {
+        match self {
+            Error::Config(e) if e.is_client_error() => Status::BadRequest,
+            Error::Decode(_) | Error::Utf(_) | Error::Json(_) => Status::BadRequest,
+            Error::RateLimited(_) => Status::TooManyRequests,
+            Error::TooManyActiveFlows(_) => Status::ServiceUnavailable,
+            Error::InvalidToken(_) => Status::BadRequest,
+            Error::UnknownPayload(_) => Status::NotFound,
+            Error::Replayed | Error::IdempotencyConflict => Status::Conflict,
+            Error::BatchTooLarge(_, _) => Status::BadRequest,
+            Error::BatchEntry(_, e) | Error::Segment(_, e) => e.status(),
+            _ => Status::InternalServerError,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::Config(e) => e.kind(),
+            Error::Decode(_) => "decode",
+            Error::Template(_) => "template",
+            Error::Json(_) => "json",
+            Error::Utf(_) => "utf",
+            Error::Jwt(_) | Error::Jose(_) => "jwt",
+            Error::Metrics(_) => "metrics",
+            Error::RateLimited(_) => "rate_limited",
+            Error::TooManyActiveFlows(_) => "too_many_active_flows",
+            Error::InvalidToken(_) => "invalid_token",
+            Error::UnknownPayload(_) => "unknown_payload",
+            Error::Replayed => "replayed",
+            Error::IdempotencyConflict => "idempotency_conflict",
+            Error::BatchTooLarge(_, _) => "batch_too_large",
+            Error::BatchEntry(_, e) | Error::Segment(_, e) => e.kind(),
+        }
+    }
+}
+
+impl<'r, 'o: 'r> rocket::response::Responder<'r, 'o> for Error {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'o> {
+        let mut body = serde_json::json!({
+            "error": self.kind(),
+            "message": self.to_string(),
+        });
+        // Errors for an entry of a batch are those of a single request, with
+        // the index of the entry
+        let error = match &self {
+            Error::BatchEntry(index, e) => {
+                body["index"] = serde_json::json!(index);
+                e.as_ref()
+            }
+            e => e,
+        };
+        // Decode errors name the segment of the browser url that was wrong
+        let error = match error {
+            Error::Segment(name, e) => {
+                body["segment"] = serde_json::json!(name);
+                e.as_ref()
+            }
+            e => e,
+        };
+        if let Error::Config(config::Error::UnknownAttributes(attributes)) = error {
+            body["attributes"] = serde_json::json!(attributes);
+        }
+        let retry_after = match error {
+            // Retry-After is in whole seconds, round up so an immediate retry
+            // is not throttled again
+            Error::RateLimited(wait) => Some(wait.as_secs() + 1),
+            _ => None,
+        };
+
+        let mut response = Custom(self.status(), Json(body)).respond_to(request)?;
+        if let Some(retry_after) = retry_after {
+            response.set_raw_header("Retry-After", retry_after.to_string());
+        }
+        Ok(response)
+    }
+}
+
+impl From<config::Error> for Error {
+    fn from(e: config::Error) -> Error {
+        Error::Config(e)
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Error {
+        Error::Decode(e)
+    }
+}
+
+impl From<askama::Error> for Error {
+    fn from(e: askama::Error) -> Error {
+        Error::Template(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(e: std::str::Utf8Error) -> Error {
+        Error::Utf(e)
+    }
+}
+
+impl From<verder_helpen_jwt::Error> for Error {
+    fn from(e: verder_helpen_jwt::Error) -> Error {
+        Error::Jwt(e)
+    }
+}
+
+impl From<josekit::JoseError> for Error {
+    fn from(e: josekit::JoseError) -> Error {
+        Error::Jose(e)
+    }
+}
+
+impl From<idempotency::Conflict> for Error {
+    fn from(_: idempotency::Conflict) -> Error {
+        Error::IdempotencyConflict
+    }
+}
+
+impl From<prometheus::Error> for Error {
+    fn from(e: prometheus::Error) -> Error {
+        Error::Metrics(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Config(e) => e.fmt(f),
+            Error::Decode(e) => e.fmt(f),
+            Error::Template(e) => e.fmt(f),
+            Error::Utf(e) => e.fmt(f),
+            Error::Json(e) => e.fmt(f),
+            Error::Jwt(e) => e.fmt(f),
+            Error::Jose(e) => e.fmt(f),
+            Error::Metrics(e) => e.fmt(f),
+            Error::RateLimited(wait) => f.write_fmt(format_args!(
+                "Too many authentication flows started, retry in {} ms",
+                wait.as_millis()
+            )),
+            Error::TooManyActiveFlows(max) => f.write_fmt(format_args!(
+                "{} authentication flows are in progress already, complete some first",
+                max
+            )),
+            Error::UnknownPayload(token) => f.write_fmt(format_args!(
+                "Unknown or expired url segment {}, start a new flow",
+                token
+            )),
+            Error::InvalidToken(e) => f.write_fmt(format_args!(
+                "Could not decrypt or verify the token, was it produced with the current keys? {}",
+                e
+            )),
+            Error::Replayed => f.write_str(
+                "This flow was already completed, or its url lacks a nonce, start a new flow",
+            ),
+            Error::IdempotencyConflict => {
+                f.write_str("This Idempotency-Key was already used for a different request")
+            }
+            Error::BatchTooLarge(size, max) => f.write_fmt(format_args!(
+                "Batch of {} requests exceeds the maximum of {}",
+                size, max
+            )),
+            Error::BatchEntry(index, e) => {
+                f.write_fmt(format_args!("Batch entry {}: {}", index, e))
+            }
+            Error::Segment(name, e) => {
+                f.write_fmt(format_args!("Could not decode the {} segment: {}", name, e))
+            }
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Config(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            Error::Template(e) => Some(e),
+            Error::Utf(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Jwt(e) => Some(e),
+            Error::Jose(e) => Some(e),
+            Error::Metrics(e) => Some(e),
+            Error::BatchEntry(_, e) | Error::Segment(_, e) => Some(e.as_ref()),
+            Error::RateLimited(_)
+            | Error::TooManyActiveFlows(_)
+            | Error::InvalidToken(_)
+            | Error::UnknownPayload(_)
+            | Error::Replayed
+            | Error::IdempotencyConflict
+            | Error::BatchTooLarge(_, _) => None,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "confirm.html")]
+struct ConfirmTemplate<'a> {
+    lang: &'a str,
+    t: &'a Messages,
+    dologin: String,
+    dologout: String,
+    hidden: Vec<(&'static str, String)>,
+    attributes: HashMap<String, String>,
+}
+
+/// Test options for a single flow. These can be given in the body of the
+/// start request, and are carried through the confirmation page to the
+/// browser handlers as query parameters. The flow id and nonce are not
+/// options, but are generated at the start of the flow and carried along the
+/// same way.
+#[derive(FromForm, Deserialize, Debug, Default)]
+struct FlowOptions {
+    status: Option<ResultStatus>,
+    delay_ms: Option<u64>,
+    delivery: Option<DeliveryMode>,
+    audience: Option<String>,
+    /// Issuer of the token, instead of the configured one.
+    issuer: Option<String>,
+    expired: Option<bool>,
+    corrupt: Option<Corruption>,
+    /// Whether the result gets a session url, instead of the configured
+    /// default.
+    with_session: Option<bool>,
+    /// Only checked when starting a flow, so not carried along.
+    policy: Option<String>,
+    /// Further attr_urls that get a copy of the result. These are carried
+    /// in the attr_url segment, together with the attr_url itself.
+    #[serde(default)]
+    attr_urls: Vec<String>,
+    #[serde(skip)]
+    flow: Option<String>,
+    #[serde(skip)]
+    nonce: Option<String>,
+}
+
+impl FlowOptions {
+    fn query_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![];
+        if let Some(status) = self.status {
+            pairs.push(("status", status.as_str().to_string()));
+        }
+        if let Some(delay_ms) = self.delay_ms {
+            pairs.push(("delay_ms", delay_ms.to_string()));
+        }
+        if let Some(delivery) = self.delivery {
+            pairs.push(("delivery", delivery.as_str().to_string()));
+        }
+        if let Some(audience) = &self.audience {
+            pairs.push(("audience", audience.clone()));
+        }
+        if let Some(issuer) = &self.issuer {
+            pairs.push(("issuer", issuer.clone()));
+        }
+        if let Some(expired) = self.expired {
+            pairs.push(("expired", expired.to_string()));
+        }
+        if let Some(corrupt) = self.corrupt {
+            pairs.push(("corrupt", corrupt.as_str().to_string()));
+        }
+        if let Some(with_session) = self.with_session {
+            pairs.push(("with_session", with_session.to_string()));
+        }
+        if let Some(flow) = &self.flow {
+            pairs.push(("flow", flow.clone()));
+        }
+        if let Some(nonce) = &self.nonce {
+            pairs.push(("nonce", nonce.clone()));
+        }
+        pairs
+    }
+
+    /// Whether the tester asked for a token that has already expired.
+    fn expired_token(&self) -> bool {
+        self.expired.unwrap_or(false)
+    }
+
+    /// The options as a query string, with the values percent-encoded as
+    /// the audience and issuer are free-form.
+    fn query_string(&self) -> String {
+        let pairs = self.query_pairs();
+        if pairs.is_empty() {
+            String::new()
+        } else {
+            let query = url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(pairs)
+                .finish();
+            format!("?{}", query)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TestAuthRequest {
+    #[serde(flatten)]
+    request: StartAuthRequest,
+    #[serde(flatten)]
+    options: FlowOptions,
+}
+
+impl TestAuthRequest {
+    /// What makes two start requests the same, for idempotency keys.
+    fn fingerprint(&self) -> String {
+        serde_json::json!({
+            "attributes": self.request.attributes,
+            "continuation": self.request.continuation,
+            "attr_url": self.request.attr_url,
+            "options": self.options.query_pairs(),
+            "policy": self.options.policy,
+            "attr_urls": self.options.attr_urls,
+        })
+        .to_string()
+    }
+}
+
+/// Response to a start request, extended with the id of the new flow. This
+/// id is included in the logs of all steps of the flow.
+#[derive(Serialize, Debug)]
+struct TestAuthResponse {
+    #[serde(flatten)]
+    response: StartAuthResponse,
+    flow_id: String,
+    #[serde(flatten)]
+    components: Option<UrlComponents>,
+}
+
+/// The parts the client url is made of, as returned in verbose mode. The
+/// segments are base64 encoded exactly as they appear in the url.
+#[derive(Serialize, Debug)]
+struct UrlComponents {
+    server_url: String,
+    attributes: String,
+    continuation: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attr_url: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "interactive.html")]
+struct InteractiveTemplate<'a> {
+    lang: &'a str,
+    t: &'a Messages,
+    submit: String,
+    attributes: HashMap<String, String>,
+}
+
+/// Consent screen of interactive flows in the consent mode. Allowing posts
+/// the values unchanged to the browser url, denying cancels the flow.
+#[derive(Template)]
+#[template(path = "consent.html")]
+struct ConsentTemplate<'a> {
+    lang: &'a str,
+    t: &'a Messages,
+    relying_party: String,
+    allow: String,
+    deny: String,
+    hidden: Vec<(&'static str, String)>,
+    attributes: HashMap<String, String>,
+}
+
+/// Page delivering the result to the relying party by posting it, for the
+/// post delivery mode. All values are html escaped by the template, the
+/// continuation and token can contain characters that need it.
+#[derive(Template)]
+#[template(path = "post_result.html")]
+struct PostResultTemplate<'a> {
+    lang: &'a str,
+    t: &'a Messages,
+    continuation: &'a str,
+    method: &'a str,
+    field: &'a str,
+    token: &'a str,
+}
+
+/// Page shown instead of returning to the relying party when a flow fails,
+/// if so configured.
+#[derive(Template)]
+#[template(path = "failed.html")]
+struct FailedTemplate<'a> {
+    lang: &'a str,
+    t: &'a Messages,
+}
+
+/// Page shown instead of returning to the relying party when its attr_url
+/// did not acknowledge the result, if so configured.
+#[derive(Template)]
+#[template(path = "unacknowledged.html")]
+struct UnacknowledgedTemplate<'a> {
+    lang: &'a str,
+    t: &'a Messages,
+}
+
+#[derive(Responder)]
+enum BrowserResponse {
+    Redirect(Redirect),
+    Page(RawHtml<String>),
+}
+
+#[derive(FromForm, Debug)]
+struct AttributeValues {
+    attributes: HashMap<String, String>,
+}
+
+/// What a post to the attr_url needs. This is owned, so a delayed post can
+/// outlive the request it belongs to.
+struct Poster {
+    config: Arc<Config>,
+    client: reqwest::Client,
+    metrics: Metrics,
+    pending_posts: PendingPosts,
+    queue: DeliveryQueue,
+}
+
+/// A result as posted to the attr_url.
+#[derive(Clone)]
+struct AttrUrlBody {
+    content_type: HeaderValue,
+    body: String,
+}
+
+impl AttrUrlBody {
+    /// The token, or if so configured, the result as plain JSON with its
+    /// attributes in the configured shape.
+    fn new(config: &Config, auth_result: &AuthResult, token: &str) -> Result<AttrUrlBody, Error> {
+        Ok(match config.attr_url_format() {
+            AttrUrlFormat::Jwt => AttrUrlBody {
+                content_type: config.attr_url_content_type().cloned().unwrap_or_else(|| {
+                    HeaderValue::from_static(match config.jwe_serialization() {
+                        TokenSerialization::Compact => "application/jwt",
+                        TokenSerialization::Json => "application/jose+json",
+                    })
+                }),
+                body: token::serialize(token, config.jwe_serialization()),
+            },
+            AttrUrlFormat::Json => AttrUrlBody {
+                content_type: HeaderValue::from_static("application/json"),
+                body: match config.attribute_result_shape() {
+                    AttributeResultShape::Object => serde_json::to_string(auth_result)?,
+                    shape => serde_json::json!({
+                        "status": auth_result.status,
+                        "attributes": token::attributes_claim(auth_result, shape),
+                        "session_url": auth_result.session_url,
+                    })
+                    .to_string(),
+                },
+            },
+        })
+    }
+}
+
+/// The managed state needed to handle a flow in the browser.
+struct Provider<'r> {
+    config: Arc<Config>,
+    client: &'r reqwest::Client,
+    metrics: &'r Metrics,
+    sessions: &'r SessionStore,
+    pending_posts: &'r PendingPosts,
+    queue: &'r DeliveryQueue,
+    payloads: &'r PayloadStore,
+    nonces: &'r NonceStore,
+    active_flows: &'r ActiveFlows,
+    step_ups: &'r StepUpStore,
+    chaos: &'r Chaos,
+}
+
+impl Provider<'_> {
+    fn poster(&self) -> Poster {
+        Poster {
+            config: self.config.clone(),
+            client: self.client.clone(),
+            metrics: self.metrics.clone(),
+            pending_posts: self.pending_posts.clone(),
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Provider<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let rocket = request.rocket();
+        match (
+            rocket.state::<SharedConfig>(),
+            rocket.state::<reqwest::Client>(),
+            rocket.state::<Metrics>(),
+            rocket.state::<SessionStore>(),
+            rocket.state::<PendingPosts>(),
+            rocket.state::<DeliveryQueue>(),
+            rocket.state::<PayloadStore>(),
+            rocket.state::<NonceStore>(),
+            rocket.state::<ActiveFlows>(),
+            rocket.state::<StepUpStore>(),
+            rocket.state::<Chaos>(),
+        ) {
+            (
+                Some(config),
+                Some(client),
+                Some(metrics),
+                Some(sessions),
+                Some(pending_posts),
+                Some(queue),
+                Some(payloads),
+                Some(nonces),
+                Some(active_flows),
+                Some(step_ups),
+                Some(chaos),
+            ) => request::Outcome::Success(Provider {
+                config: config.snapshot(),
+                client,
+                metrics,
+                sessions,
+                pending_posts,
+                queue,
+                payloads,
+                nonces,
+                active_flows,
+                step_ups,
+                chaos,
+            }),
+            _ => request::Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+#[derive(FromForm, Debug)]
+struct SessionUpdateData {
+    #[field(name = "type")]
+    typeval: Activity,
+}
+
+#[get("/confirm/<attributes>/<continuation>/<attr_url>?<options..>")]
+#[tracing::instrument(skip_all, fields(flow_id = options.flow.as_deref()))]
+async fn confirm_oob(
+    config: ConfigSnapshot,
+    payloads: &State<PayloadStore>,
+    server_url: ServerUrl,
+    attributes: String,
+    continuation: String,
+    attr_url: String,
+    options: FlowOptions,
+    language: AcceptLanguage,
+) -> Result<RawHtml<String>, Error> {
+    let values = config.map_attributes(&decode_attributes(&config, payloads, &attributes)?)?;
+    let locale = config.locales().select(&language);
+    let template = ConfirmTemplate {
+        lang: locale.lang,
+        t: locale.messages,
+        dologin: format!(
+            "{}/browser/{}/{}/{}",
+            server_url.as_str(),
+            attributes,
+            continuation,
+            attr_url
+        ),
+        dologout: format!(
+            "{}/browser/{}/{}/{}/cancel",
+            server_url.as_str(),
+            attributes,
+            continuation,
+            attr_url
+        ),
+        hidden: options.query_pairs(),
+        attributes: values,
+    };
+    let output = template.render()?;
+    Ok(RawHtml(output))
+}
+
+#[get("/confirm/<attributes>/<continuation>?<options..>")]
+#[tracing::instrument(skip_all, fields(flow_id = options.flow.as_deref()))]
+async fn confirm_ib(
+    config: ConfigSnapshot,
+    payloads: &State<PayloadStore>,
+    server_url: ServerUrl,
+    attributes: String,
+    continuation: String,
+    options: FlowOptions,
+    language: AcceptLanguage,
+) -> Result<RawHtml<String>, Error> {
+    let values = config.map_attributes(&decode_attributes(&config, payloads, &attributes)?)?;
+    let locale = config.locales().select(&language);
+    let template = ConfirmTemplate {
+        lang: locale.lang,
+        t: locale.messages,
+        dologin: format!(
+            "{}/browser/{}/{}",
+            server_url.as_str(),
+            attributes,
+            continuation
+        ),
+        dologout: format!(
+            "{}/browser/{}/{}/cancel",
+            server_url.as_str(),
+            attributes,
+            continuation
+        ),
+        hidden: options.query_pairs(),
+        attributes: values,
+    };
+    let output = template.render()?;
+    Ok(RawHtml(output))
+}
+
+/// Key of the result attribute holding the attributes as requested, when
+/// `include_requested_attributes` is enabled. Results can't carry anything
+/// but attributes, so this is encoded as a JSON list like multiple values.
+const REQUESTED_ATTRIBUTES: &str = "_requested_attributes";
+
+/// Build the result for a flow. Values entered on the interactive page are
+/// passed as overrides, and replace the configured values of the requested
+/// attributes.
+fn build_auth_result(
+    provider: &Provider<'_>,
+    attributes: &[String],
+    options: &FlowOptions,
+    overrides: Option<HashMap<String, String>>,
+    step: Step,
+) -> Result<AuthResult, Error> {
+    let config = &provider.config;
+    if options.status.unwrap_or_else(|| config.default_status()) == ResultStatus::Failed {
+        return Ok(failed_auth_result());
+    }
+    if config.failure_rate() > 0.0 {
+        let fail = provider.chaos.fail(config.failure_rate());
+        info!(
+            randomly_failed = fail,
+            "Decided whether to fail the flow at random"
+        );
+        if fail {
+            return Ok(failed_auth_result());
+        }
+    }
+    if config.fail_on_missing_attributes() {
+        let missing = config.missing_attributes(attributes);
+        if !missing.is_empty() {
+            warn!(?missing, "Failing flow, requested attributes are missing");
+            return Ok(failed_auth_result());
+        }
+    }
+
+    let mut values = config.map_attributes(attributes)?;
+    if let Some(overrides) = overrides {
+        for (key, value) in overrides {
+            if let Some(entry) = values.get_mut(&key) {
+                *entry = value;
+            }
+        }
+    }
+    if step == Step::Initial {
+        values.retain(|attribute, _| config.step_up_attributes().contains(attribute));
+    }
+    if config.include_requested_attributes() {
+        values.insert(
+            REQUESTED_ATTRIBUTES.to_string(),
+            serde_json::to_string(attributes)?,
+        );
+    }
+
+    Ok(AuthResult {
+        status: AuthStatus::Success,
+        attributes: Some(values),
+        session_url: if options
+            .with_session
+            .unwrap_or_else(|| config.with_session())
+        {
+            let id = provider.sessions.create(options.flow.as_deref());
+            Some(format!("{}/session/{}/update", config.internal_url(), id))
+        } else {
+            None
+        },
+    })
+}
+
+/// Produce the token for a result, as asked for by the options of the flow,
+/// including any deliberate corruption.
+fn issue_token(
+    config: &Config,
+    options: &FlowOptions,
+    auth_result: &AuthResult,
+) -> Result<String, Error> {
+    let token = token::issue(
+        config,
+        options.audience.as_deref(),
+        options.issuer.as_deref(),
+        auth_result,
+        options.expired_token(),
+    )?;
+    match options.corrupt {
+        Some(_) if !config.fault_injection() => Err(config::Error::FaultInjectionDisabled.into()),
+        Some(corruption) => {
+            warn!(corruption = corruption.as_str(), "Corrupting token");
+            Ok(token::corrupt(&token, corruption))
+        }
+        None => Ok(token),
+    }
+}
+
+fn failed_auth_result() -> AuthResult {
+    // Real providers don't disclose any attributes on failure
+    AuthResult {
+        status: AuthStatus::Failed,
+        attributes: None,
+        session_url: None,
+    }
+}
+
+/// The page of an interactive flow, as configured. The browser url is where
+/// the page submits to, consent screens cancel with its cancel url.
+fn interactive_page(
+    provider: &Provider<'_>,
+    attributes: &str,
+    continuation: &str,
+    browser_url: String,
+    options: &FlowOptions,
+    language: &AcceptLanguage,
+) -> Result<RawHtml<String>, Error> {
+    let values = provider.config.map_attributes(&decode_attributes(
+        &provider.config,
+        provider.payloads,
+        attributes,
+    )?)?;
+    let locale = provider.config.locales().select(language);
+    match provider.config.interactive_mode() {
+        InteractiveMode::Form => {
+            let template = InteractiveTemplate {
+                lang: locale.lang,
+                t: locale.messages,
+                submit: format!("{}{}", browser_url, options.query_string()),
+                attributes: values,
+            };
+            Ok(RawHtml(template.render()?))
+        }
+        InteractiveMode::Consent => {
+            // The relying party is known by the host it wants the user back at
+            let continuation = provider.config.resolve_continuation(&decode_text(
+                provider.payloads,
+                continuation,
+                "continuation",
+            )?)?;
+            let relying_party = Url::parse(&continuation)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .unwrap_or(continuation);
+            let template = ConsentTemplate {
+                lang: locale.lang,
+                t: locale.messages,
+                relying_party,
+                allow: format!("{}{}", browser_url, options.query_string()),
+                deny: format!("{}/cancel", browser_url),
+                hidden: options.query_pairs(),
+                attributes: values,
+            };
+            Ok(RawHtml(template.render()?))
+        }
+    }
+}
+
+/// Simulate a slow identity provider. The browser handlers call this after
+/// the attributes have been mapped, but before the result is signed,
+/// reported to the attr_url and the user is redirected, so the measured
+/// latency covers the complete remainder of the flow. A delay requested for
+/// the flow takes precedence over the configured distribution.
+async fn response_delay(provider: &Provider<'_>, options: &FlowOptions) {
+    let config = &provider.config;
+    let delay_ms = options.delay_ms.unwrap_or_else(|| {
+        provider.chaos.delay_ms(
+            config.response_delay_distribution(),
+            config.response_delay_ms(),
+        )
+    });
+    if delay_ms > 0 {
+        rocket::tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+}
+
+/// Session update for session urls handed out before sessions had ids.
+/// These can't be linked to a session, so are never known.
+#[post("/session/update?<typedata..>")]
+async fn session_update(
+    _signed: SignedUpdate,
+    metrics: &State<Metrics>,
+    typedata: SessionUpdateData,
+) -> Json<SessionUpdateResult> {
+    info!(
+        activity = typedata.typeval.name().as_str(),
+        known_activity = typedata.typeval.is_known(),
+        "Session update received"
+    );
+    metrics.session_updates.inc();
+    Json(SessionUpdateResult {
+        id: None,
+        known: false,
+        state: None,
+    })
+}
+
+#[post("/session/<id>/update?<typedata..>")]
+async fn session_update_id(
+    _signed: SignedUpdate,
+    metrics: &State<Metrics>,
+    sessions: &State<SessionStore>,
+    id: String,
+    typedata: SessionUpdateData,
+) -> Option<Json<SessionUpdateResult>> {
+    // Session ids are the ids of the flows they belong to
+    info!(
+        session = id.as_str(),
+        flow_id = id.as_str(),
+        activity = typedata.typeval.name().as_str(),
+        known_activity = typedata.typeval.is_known(),
+        "Session update received"
+    );
+    metrics.session_updates.inc();
+    let state = sessions.record(&id, typedata.typeval.name())?;
+    Some(Json(SessionUpdateResult {
+        id: Some(id),
+        known: true,
+        state: Some(state),
+    }))
+}
+
+#[get("/session/<id>")]
+async fn session_status(sessions: &State<SessionStore>, id: String) -> Option<Json<SessionStatus>> {
+    sessions.status(&id).map(Json)
+}
+
+#[get("/session/<id>/history")]
+async fn session_history(
+    sessions: &State<SessionStore>,
+    id: String,
+) -> Option<Json<Vec<ActivityRecord>>> {
+    sessions.history(&id).map(Json)
+}
+
+// Ranked below the inline cancel route, whose last segment is static
+#[get(
+    "/browser/<attributes>/<continuation>/<attr_url>?<options..>",
+    rank = 1
+)]
+#[tracing::instrument(skip_all, fields(flow_id = options.flow.as_deref()))]
+async fn user_oob(
+    provider: Provider<'_>,
+    server_url: ServerUrl,
+    attributes: String,
+    continuation: String,
+    attr_url: String,
+    options: FlowOptions,
+    language: AcceptLanguage,
+) -> Result<BrowserResponse, Error> {
+    if provider.config.interactive() {
+        let browser_url = format!(
+            "{}/browser/{}/{}/{}",
+            server_url.as_str(),
+            attributes,
+            continuation,
+            attr_url
+        );
+        return Ok(BrowserResponse::Page(interactive_page(
+            &provider,
+            &attributes,
+            &continuation,
+            browser_url,
+            &options,
+            &language,
+        )?));
+    }
+
+    complete_oob(
+        &provider,
+        attributes,
+        continuation,
+        attr_url,
+        &options,
+        None,
+        &language,
+    )
+    .await
+}
+
+#[post(
+    "/browser/<attributes>/<continuation>/<attr_url>?<options..>",
+    data = "<values>"
+)]
+#[tracing::instrument(skip_all, fields(flow_id = options.flow.as_deref()))]
+async fn user_oob_submit(
+    provider: Provider<'_>,
+    attributes: String,
+    continuation: String,
+    attr_url: String,
+    options: FlowOptions,
+    values: Form<AttributeValues>,
+    language: AcceptLanguage,
+) -> Result<BrowserResponse, Error> {
+    complete_oob(
+        &provider,
+        attributes,
+        continuation,
+        attr_url,
+        &options,
+        Some(values.into_inner().attributes),
+        &language,
+    )
+    .await
+}
+
+async fn complete_oob(
+    provider: &Provider<'_>,
+    attributes: String,
+    continuation: String,
+    attr_url: String,
+    options: &FlowOptions,
+    overrides: Option<HashMap<String, String>>,
+    language: &AcceptLanguage,
+) -> Result<BrowserResponse, Error> {
+    consume_nonce(provider, options)?;
+    let attr_urls = decode_attr_urls(provider.payloads, &attr_url)?;
+
+    let prepared =
+        match prepare_result(provider, attributes, continuation, options, overrides).await {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                report_failure(provider, &attr_urls, options, &e).await;
+                return Err(e);
+            }
+        };
+
+    let body = AttrUrlBody::new(&provider.config, &prepared.auth_result, &prepared.token)?;
+    let acknowledged = deliver_results(
+        provider.poster(),
+        attr_urls,
+        body,
+        provider.config.attr_url_delay(),
+    )
+    .await;
+    provider
+        .metrics
+        .flows_completed
+        .with_label_values(&["oob", prepared.status])
+        .inc();
+
+    if !acknowledged && provider.config.attr_url_ack_failure_page() {
+        info!(
+            continuation = prepared.continuation.as_str(),
+            "Showing error page instead of returning to {}", prepared.continuation
+        );
+        let locale = provider.config.locales().select(language);
+        let template = UnacknowledgedTemplate {
+            lang: locale.lang,
+            t: locale.messages,
+        };
+        return Ok(BrowserResponse::Page(RawHtml(template.render()?)));
+    }
+
+    // The relying party has the failed result at this point, only the user
+    // is kept from returning
+    if let Some(page) = failed_page(&provider.config, &prepared, language)? {
+        return Ok(page);
+    }
+
+    info!(
+        continuation = prepared.continuation.as_str(),
+        "Redirecting user to {}", prepared.continuation
+    );
+    Ok(BrowserResponse::Redirect(
+        provider
+            .config
+            .redirect_status()
+            .redirect(prepared.continuation),
+    ))
+}
+
+/// The page to show instead of returning to the relying party, when the
+/// flow failed and failures are configured to show an error page.
+fn failed_page(
+    config: &Config,
+    prepared: &PreparedResult,
+    language: &AcceptLanguage,
+) -> Result<Option<BrowserResponse>, Error> {
+    if prepared.status != status_label(&AuthStatus::Failed)
+        || config.failed_status_behavior() != FailedStatusBehavior::ErrorPage
+    {
+        return Ok(None);
+    }
+
+    info!(
+        continuation = prepared.continuation.as_str(),
+        "Showing error page instead of returning to {}", prepared.continuation
+    );
+    let locale = config.locales().select(language);
+    let template = FailedTemplate {
+        lang: locale.lang,
+        t: locale.messages,
+    };
+    Ok(Some(BrowserResponse::Page(RawHtml(template.render()?))))
+}
+
+/// Stop counting a flow as active, now the user reached its end.
+fn complete_flow(provider: &Provider<'_>, options: &FlowOptions) {
+    if let Some(flow) = &options.flow {
+        provider.active_flows.complete(flow);
+    }
+}
+
+/// With replay protection, accept the nonce of a flow only once, before its
+/// result is produced. Urls without a nonce were not handed out with replay
+/// protection enabled, and are rejected as well.
+fn consume_nonce(provider: &Provider<'_>, options: &FlowOptions) -> Result<(), Error> {
+    let ttl = match provider.config.replay_protection_ttl() {
+        Some(ttl) => ttl,
+        None => return Ok(()),
+    };
+    match &options.nonce {
+        Some(nonce) if provider.nonces.consume(nonce, ttl) => Ok(()),
+        _ => {
+            warn!(nonce = options.nonce.as_deref(), "Rejecting replayed flow");
+            Err(Error::Replayed)
+        }
+    }
+}
+
+/// A signed result, ready for delivery to the relying party.
+struct PreparedResult {
+    status: &'static str,
+    auth_result: AuthResult,
+    token: String,
+    continuation: String,
+}
+
+async fn prepare_result(
+    provider: &Provider<'_>,
+    attributes: String,
+    continuation: String,
+    options: &FlowOptions,
+    overrides: Option<HashMap<String, String>>,
+) -> Result<PreparedResult, Error> {
+    let config = &provider.config;
+    let attributes = decode_attributes(config, provider.payloads, &attributes)?;
+    debug!(?attributes, "Decoded requested attributes");
+    let step = match (config.step_up_ttl(), options.flow.as_deref()) {
+        (Some(ttl), Some(flow)) => provider.step_ups.advance(flow, ttl),
+        _ => Step::Final,
+    };
+    // The relying party sends the user back for the second round of a
+    // step-up, so only that round ends the flow
+    if step == Step::Final {
+        complete_flow(provider, options);
+    }
+    let auth_result = build_auth_result(provider, &attributes, options, overrides, step)?;
+    if config.log_auth_results() {
+        log_auth_result(&auth_result);
+    }
+    response_delay(provider, options).await;
+    let status = status_label(&auth_result.status);
+    let token = issue_token(config, options, &auth_result)?;
+
+    let mut continuation = config.resolve_continuation(&decode_text(
+        provider.payloads,
+        &continuation,
+        "continuation",
+    )?)?;
+    if step == Step::Initial {
+        info!("Requiring step-up for the full result");
+        let mut url = Url::parse(&continuation).map_err(config::Error::from)?;
+        url.query_pairs_mut().append_pair("step_up", "required");
+        continuation = url.into();
+    }
+
+    Ok(PreparedResult {
+        status,
+        auth_result,
+        token,
+        continuation,
+    })
+}
+
+/// Log the full contents of a result. These contain personal data, so this
+/// is only done when enabled in the configuration.
+fn log_auth_result(auth_result: &AuthResult) {
+    let mut attribute_keys: Vec<&String> = auth_result
+        .attributes
+        .iter()
+        .flat_map(|attributes| attributes.keys())
+        .collect();
+    attribute_keys.sort();
+    match serde_json::to_string(auth_result) {
+        Ok(json) => debug!(
+            status = status_label(&auth_result.status),
+            ?attribute_keys,
+            with_session = auth_result.session_url.is_some(),
+            "Constructed auth result {}",
+            json
+        ),
+        Err(e) => warn!("Could not serialize auth result for logging: {}", e),
+    }
+}
+
+/// Let the relying party know the flow failed, even though the error is
+/// reported to the user's browser. Only a generic failed result is sent, the
+/// error itself is never included in the token.
+async fn report_failure(
+    provider: &Provider<'_>,
+    attr_urls: &[String],
+    options: &FlowOptions,
+    error: &Error,
+) {
+    warn!(
+        ?attr_urls,
+        "Flow failed, reporting failure to attr_url: {}", error
+    );
+    provider
+        .metrics
+        .flows_completed
+        .with_label_values(&["oob", "error"])
+        .inc();
+    let auth_result = failed_auth_result();
+    match issue_token(&provider.config, options, &auth_result)
+        .and_then(|token| AttrUrlBody::new(&provider.config, &auth_result, &token))
+    {
+        Ok(body) => {
+            deliver_results(provider.poster(), attr_urls.to_vec(), body, None).await;
+        }
+        Err(e) => warn!(?attr_urls, "Could not sign failure result: {}", e),
+    }
+}
+
+fn status_label(status: &AuthStatus) -> &'static str {
+    match status {
+        AuthStatus::Success => "success",
+        _ => "failed",
+    }
+}
+
+/// Post the result to the attr_urls. After a delay, or when deliveries are
+/// queued, this happens in the background, so the user is sent back without
+/// waiting for it. Those deliveries count as acknowledged, as their outcome
+/// is not known yet.
+async fn deliver_results(
+    poster: Poster,
+    attr_urls: Vec<String>,
+    body: AttrUrlBody,
+    delay: Option<Duration>,
+) -> bool {
+    if delay.is_none() && !poster.queue.is_bounded() {
+        return report_results(&poster, &attr_urls, body).await;
+    }
+    rocket::tokio::spawn(
+        async move {
+            // Shutdown waits for posts in the background as well
+            let _pending = poster.pending_posts.track();
+            if let Some(delay) = delay {
+                rocket::tokio::time::sleep(delay).await;
+            }
+            report_results(&poster, &attr_urls, body).await;
+        }
+        .instrument(tracing::Span::current()),
+    );
+    true
+}
+
+/// Post the result to each of the attr_urls concurrently, a failure for one
+/// doesn't affect the others. Returns whether all of them acknowledged it.
+async fn report_results(poster: &Poster, attr_urls: &[String], body: AttrUrlBody) -> bool {
+    let posts = attr_urls
+        .iter()
+        .map(|attr_url| report_result(poster, attr_url, body.clone()));
+    join_all(posts)
+        .await
+        .into_iter()
+        .all(|acknowledged| acknowledged)
+}
+
+/// Post the result to the attr_url. Transport errors and 5xx responses are
+/// retried with exponential backoff. Failures are only logged, so the
+/// browser side of the flow is not affected by them, unless acknowledgement
+/// is expected. Returns false when it is expected but the attr_url did not
+/// respond with a 2xx.
+async fn report_result(poster: &Poster, attr_url: &str, body: AttrUrlBody) -> bool {
+    let Poster {
+        config,
+        client,
+        metrics,
+        pending_posts,
+        queue,
+    } = poster;
+    let AttrUrlBody { content_type, body } = body;
+    // Retries count as part of the post, shutdown waits for them as well
+    let _pending = pending_posts.track();
+    // Retries keep the slot, they are still the same delivery
+    metrics.attr_url_queue_depth.inc();
+    let _slot = queue.enter().await;
+    metrics.attr_url_queue_depth.dec();
+
+    let mut attempt: u32 = 0;
+    loop {
+        let mut request = client
+            .request(config.attr_url_method().method(), attr_url)
+            .headers(config.attr_url_headers().clone())
+            .header(CONTENT_TYPE, content_type.clone())
+            .body(body.clone());
+        // A timeout is reported as a transport error, and handled as such
+        if let Some(timeout) = config.attr_url_timeout() {
+            request = request.timeout(timeout);
+        }
+
+        let timer = metrics.attr_url_post_duration.start_timer();
+        let result = request.send().await;
+        timer.observe_duration();
+        let outcome = match &result {
+            Ok(response) if !response.status().is_server_error() => "success",
+            _ => "failure",
+        };
+        metrics.attr_url_posts.with_label_values(&[outcome]).inc();
+
+        let failure = match result {
+            Ok(response) if response.status().is_server_error() => {
+                format!("attr_url responded with {}", response.status())
+            }
+            Ok(response) => {
+                // The JSON body is personal data, unlike the encrypted token
+                match config.attr_url_format() {
+                    AttrUrlFormat::Jwt => {
+                        info!(
+                            attr_url,
+                            "Reported result jwe {} to {}",
+                            config.logged_token(&body),
+                            attr_url
+                        )
+                    }
+                    AttrUrlFormat::Json => {
+                        info!(attr_url, "Reported result as JSON to {}", attr_url)
+                    }
+                }
+                if !config.attr_url_expect_ack() {
+                    return true;
+                }
+
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                if status.is_success() {
+                    info!(
+                        attr_url,
+                        status = status.as_u16(),
+                        "attr_url acknowledged the result with {}: {}",
+                        status,
+                        body
+                    );
+                    return true;
+                }
+                error!(
+                    attr_url,
+                    status = status.as_u16(),
+                    "attr_url did not acknowledge the result, it responded with {}: {}",
+                    status,
+                    body
+                );
+                return false;
+            }
+            Err(e) => e.to_string(),
+        };
+
+        if attempt >= config.attr_url_retries() {
+            if config.attr_url_expect_ack() {
+                error!(
+                    attr_url,
+                    attempts = attempt + 1,
+                    exhausted = true,
+                    "Failure reporting results, retries exhausted after {} attempt(s): {}",
+                    attempt + 1,
+                    failure
+                );
+                return false;
+            }
+            warn!(
+                attr_url,
+                attempts = attempt + 1,
+                exhausted = true,
+                "Failure reporting results, retries exhausted after {} attempt(s): {}",
+                attempt + 1,
+                failure
+            );
+            return true;
+        }
+
+        let delay_ms = config
+            .attr_url_retry_base_delay_ms()
+            .saturating_mul(2u64.saturating_pow(attempt));
+        warn!(
+            attr_url,
+            attempts = attempt + 1,
+            exhausted = false,
+            "Failure reporting results (attempt {}), retrying in {} ms: {}",
+            attempt + 1,
+            delay_ms,
+            failure
+        );
+        rocket::tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        attempt += 1;
+    }
+}
+
+#[get("/cancel/browser/<continuation>")]
+async fn cancel_oob(
+    config: ConfigSnapshot,
+    payloads: &State<PayloadStore>,
+    continuation: String,
+) -> Result<Redirect, Error> {
+    let continuation =
+        config.resolve_continuation(&decode_text(payloads, &continuation, "continuation")?)?;
+
+    info!(
+        continuation = continuation.as_str(),
+        "Redirecting user to {}", continuation
+    );
+    Ok(config.redirect_status().redirect(continuation))
+}
+
+#[get("/browser/<attributes>/<continuation>?<options..>")]
+#[tracing::instrument(skip_all, fields(flow_id = options.flow.as_deref()))]
+async fn user_inline(
+    provider: Provider<'_>,
+    server_url: ServerUrl,
+    attributes: String,
+    continuation: String,
+    options: FlowOptions,
+    language: AcceptLanguage,
+) -> Result<BrowserResponse, Error> {
+    if provider.config.interactive() {
+        let browser_url = format!(
+            "{}/browser/{}/{}",
+            server_url.as_str(),
+            attributes,
+            continuation
+        );
+        return Ok(BrowserResponse::Page(interactive_page(
+            &provider,
+            &attributes,
+            &continuation,
+            browser_url,
+            &options,
+            &language,
+        )?));
+    }
+
+    complete_inline(
+        &provider,
+        attributes,
+        continuation,
+        &options,
+        None,
+        &language,
+    )
+    .await
+}
+
+#[post("/browser/<attributes>/<continuation>?<options..>", data = "<values>")]
+#[tracing::instrument(skip_all, fields(flow_id = options.flow.as_deref()))]
+async fn user_inline_submit(
+    provider: Provider<'_>,
+    attributes: String,
+    continuation: String,
+    options: FlowOptions,
+    values: Form<AttributeValues>,
+    language: AcceptLanguage,
+) -> Result<BrowserResponse, Error> {
+    complete_inline(
+        &provider,
+        attributes,
+        continuation,
+        &options,
+        Some(values.into_inner().attributes),
+        &language,
+    )
+    .await
+}
+
+async fn complete_inline(
+    provider: &Provider<'_>,
+    attributes: String,
+    continuation: String,
+    options: &FlowOptions,
+    overrides: Option<HashMap<String, String>>,
+    language: &AcceptLanguage,
+) -> Result<BrowserResponse, Error> {
+    consume_nonce(provider, options)?;
+    let prepared = prepare_result(provider, attributes, continuation, options, overrides).await?;
+
+    provider
+        .metrics
+        .flows_completed
+        .with_label_values(&["inline", prepared.status])
+        .inc();
+    if let Some(page) = failed_page(&provider.config, &prepared, language)? {
+        return Ok(page);
+    }
+    deliver_result(
+        &provider.config,
+        options,
+        &prepared.continuation,
+        &prepared.token,
+        language,
+    )
+}
+
+/// Send the user back to the relying party with the result, in the query or
+/// fragment of a redirect, or in an automatically submitted form.
+fn deliver_result(
+    config: &Config,
+    options: &FlowOptions,
+    continuation: &str,
+    auth_result: &str,
+    language: &AcceptLanguage,
+) -> Result<BrowserResponse, Error> {
+    let delivery = options.delivery.unwrap_or_else(|| config.delivery_mode());
+    if config.jwe_serialization() != TokenSerialization::Compact && delivery != DeliveryMode::Post {
+        return Err(config::Error::JsonSerializationInUrl(delivery.as_str()).into());
+    }
+    match delivery {
+        DeliveryMode::Redirect => Ok(BrowserResponse::Redirect(redirect_with_result(
+            config,
+            continuation,
+            auth_result,
+        ))),
+        DeliveryMode::Fragment => {
+            info!(
+                continuation,
+                "Redirecting user to {} with auth result {} in the fragment",
+                continuation,
+                config.logged_token(auth_result)
+            );
+            let parameter = config.result_parameter_name();
+            let auth_result: String =
+                url::form_urlencoded::byte_serialize(auth_result.as_bytes()).collect();
+            // Keep an existing fragment, adding the result as another parameter
+            let separator = if continuation.contains('#') { '&' } else { '#' };
+            Ok(BrowserResponse::Redirect(
+                config.redirect_status().redirect(format!(
+                    "{}{}{}={}",
+                    continuation, separator, parameter, auth_result
+                )),
+            ))
+        }
+        DeliveryMode::Post => {
+            info!(
+                continuation,
+                "Posting auth result {} to {}",
+                config.logged_token(auth_result),
+                continuation
+            );
+            let token = token::serialize(auth_result, config.jwe_serialization());
+            let locale = config.locales().select(language);
+            let template = PostResultTemplate {
+                lang: locale.lang,
+                t: locale.messages,
+                continuation,
+                method: config.post_form_method().as_str(),
+                field: config.post_field_name(),
+                token: &token,
+            };
+            Ok(BrowserResponse::Page(RawHtml(template.render()?)))
+        }
+    }
+}
+
+/// Decode the attr_url segment into the urls to post the result to. Flows
+/// with several attr_urls carry them as a JSON list, which a url can't be
+/// mistaken for.
+fn decode_attr_urls(payloads: &PayloadStore, segment: &str) -> Result<Vec<String>, Error> {
+    let attr_url = decode_text(payloads, segment, "attr_url")?;
+    if attr_url.starts_with('[') {
+        serde_json::from_str(&attr_url).map_err(|e| Error::Segment("attr_url", Box::new(e.into())))
+    } else {
+        Ok(vec![attr_url])
+    }
+}
+
+/// Decode a base64 path segment. We hand out url-safe base64 ourselves, but
+/// some clients construct urls with standard base64, so that is accepted too.
+fn decode_segment(segment: &str) -> Result<Vec<u8>, Error> {
+    let error = match base64::decode_config(segment, URL_SAFE_NO_PAD) {
+        Ok(decoded) => return Ok(decoded),
+        Err(e) => e,
+    };
+    for config in [base64::URL_SAFE, base64::STANDARD_NO_PAD, base64::STANDARD] {
+        if let Ok(decoded) = base64::decode_config(segment, config) {
+            return Ok(decoded);
+        }
+    }
+    // Report the error for the alphabet we use ourselves
+    Err(error.into())
+}
+
+/// Decode a path segment, which is either a base64 encoded payload, or a
+/// reference to a payload kept in the payload store.
+fn decode_payload(payloads: &PayloadStore, segment: &str) -> Result<Vec<u8>, Error> {
+    match segment.strip_prefix(STORED_PREFIX) {
+        Some(token) => payloads
+            .get(token)
+            .ok_or_else(|| Error::UnknownPayload(segment.to_string())),
+        None => decode_segment(segment),
+    }
+}
+
+/// Decode the attributes segment of a browser url, a JSON array of attribute
+/// names. With lenient parsing, a comma separated list is accepted too.
+fn decode_attributes(
+    config: &Config,
+    payloads: &PayloadStore,
+    segment: &str,
+) -> Result<Vec<String>, Error> {
+    let in_segment = |e: Error| Error::Segment("attributes", Box::new(e));
+    let decoded = decode_payload(payloads, segment).map_err(in_segment)?;
+    let error = match serde_json::from_slice(&decoded) {
+        Ok(attributes) => return Ok(attributes),
+        Err(e) => e,
+    };
+    match std::str::from_utf8(&decoded) {
+        Ok(list) if config.lenient_attribute_parsing() => Ok(list
+            .split(',')
+            .map(str::trim)
+            .filter(|attribute| !attribute.is_empty())
+            .map(str::to_string)
+            .collect()),
+        _ => Err(in_segment(error.into())),
+    }
+}
+
+/// Decode a path segment holding text, like the continuation and attr_url.
+/// Errors name the segment, so clients can tell which one they got wrong.
+fn decode_text(
+    payloads: &PayloadStore,
+    segment: &str,
+    name: &'static str,
+) -> Result<String, Error> {
+    let in_segment = |e: Error| Error::Segment(name, Box::new(e));
+    let decoded = decode_payload(payloads, segment).map_err(in_segment)?;
+    String::from_utf8(decoded).map_err(|e| in_segment(e.utf8_error().into()))
+}
+
+/// Placeholder for the result in templated continuations.
+const RESULT_PLACEHOLDER: &str = "{result}";
+
+/// The placeholder as it ends up in relative continuations, which are
+/// percent-encoded when resolved against the base url.
+const ENCODED_RESULT_PLACEHOLDER: &str = "%7Bresult%7D";
+
+fn redirect_with_result(config: &Config, continuation: &str, auth_result: &str) -> Redirect {
+    info!(
+        continuation,
+        "Redirecting user to {} with auth result {}",
+        continuation,
+        config.logged_token(auth_result)
+    );
+    let parameter = config.result_parameter_name();
+    // Real tokens only contain url-safe characters, but deterministic tokens
+    // contain JSON
+    let auth_result: String =
+        url::form_urlencoded::byte_serialize(auth_result.as_bytes()).collect();
+    let status = config.redirect_status();
+    if config.continuation_template() {
+        for placeholder in [RESULT_PLACEHOLDER, ENCODED_RESULT_PLACEHOLDER] {
+            if continuation.contains(placeholder) {
+                return status.redirect(continuation.replace(placeholder, &auth_result));
+            }
+        }
+    }
+    if continuation.contains('?') {
+        status.redirect(format!("{}&{}={}", continuation, parameter, auth_result))
+    } else {
+        status.redirect(format!("{}?{}={}", continuation, parameter, auth_result))
+    }
+}
+
+#[get("/browser/<_>/<continuation>/cancel?<options..>")]
+#[tracing::instrument(skip_all, fields(flow_id = options.flow.as_deref()))]
+async fn user_inline_cancel(
+    provider: Provider<'_>,
+    continuation: String,
+    options: FlowOptions,
+    language: AcceptLanguage,
+) -> Result<BrowserResponse, Error> {
+    consume_nonce(&provider, &options)?;
+    complete_flow(&provider, &options);
+    let config = &provider.config;
+    let auth_result = issue_token(config, &options, &failed_auth_result())?;
+
+    let continuation = config.resolve_continuation(&decode_text(
+        provider.payloads,
+        &continuation,
+        "continuation",
+    )?)?;
+
+    info!(
+        continuation = continuation.as_str(),
+        "User cancelled authentication"
+    );
+    provider
+        .metrics
+        .flows_completed
+        .with_label_values(&["inline", "cancelled"])
+        .inc();
+    deliver_result(config, &options, &continuation, &auth_result, &language)
+}
+
+#[get("/browser/<_>/<continuation>/<attr_url>/cancel?<options..>")]
+#[tracing::instrument(skip_all, fields(flow_id = options.flow.as_deref()))]
+async fn user_oob_cancel(
+    provider: Provider<'_>,
+    continuation: String,
+    attr_url: String,
+    options: FlowOptions,
+) -> Result<Redirect, Error> {
+    consume_nonce(&provider, &options)?;
+    complete_flow(&provider, &options);
+    let config = &provider.config;
+    let failed = failed_auth_result();
+    let auth_result = issue_token(config, &options, &failed)?;
+
+    let continuation = config.resolve_continuation(&decode_text(
+        provider.payloads,
+        &continuation,
+        "continuation",
+    )?)?;
+
+    let attr_urls = decode_attr_urls(provider.payloads, &attr_url)?;
+
+    info!(
+        continuation = continuation.as_str(),
+        ?attr_urls,
+        "User cancelled authentication"
+    );
+    let body = AttrUrlBody::new(config, &failed, &auth_result)?;
+    deliver_results(provider.poster(), attr_urls, body, None).await;
+    provider
+        .metrics
+        .flows_completed
+        .with_label_values(&["oob", "cancelled"])
+        .inc();
+
+    info!(
+        continuation = continuation.as_str(),
+        "Redirecting user to {}", continuation
+    );
+    Ok(config.redirect_status().redirect(continuation))
+}
+
+/// Check that a flow can be started for a request, without starting it.
+fn validate_start(
+    config: &Config,
+    request: &StartAuthRequest,
+    options: &FlowOptions,
+) -> Result<(), Error> {
+    config.verify_attributes(&request.attributes, options.policy.as_deref())?;
+    config.verify_audience(options.audience.as_deref())?;
+    if let Some(issuer) = &options.issuer {
+        config::verify_issuer(issuer)?;
+    }
+    if options.corrupt.is_some() && !config.fault_injection() {
+        return Err(config::Error::FaultInjectionDisabled.into());
+    }
+    if request.attributes.is_empty() && !config.allow_empty_attributes() {
+        return Err(config::Error::NoAttributes.into());
+    }
+    config.resolve_continuation(&request.continuation)?;
+    for attr_url in request.attr_url.iter().chain(&options.attr_urls) {
+        config.verify_url(attr_url, "attr_url")?;
+    }
+    Ok(())
+}
+
+#[derive(Responder)]
+enum StartResponse {
+    Started(Json<TestAuthResponse>),
+    /// The response for an idempotency key, whether just started or stored.
+    Idempotent(Json<serde_json::Value>),
+    Validated(Json<serde_json::Value>),
+}
+
+/// Take the rate limit budget and the active flow slots for `count` new
+/// flows, returning their ids. Either all of them are admitted or none.
+fn admit_flows(
+    config: &Config,
+    limiter: &StartLimiter,
+    active_flows: &ActiveFlows,
+    count: usize,
+) -> Result<Vec<String>, Error> {
+    if let Some(max_starts) = config.max_starts_per_minute() {
+        limiter
+            .acquire(max_starts, count)
+            .map_err(Error::RateLimited)?;
+    }
+    let flow_ids: Vec<String> = (0..count).map(|_| Uuid::new_v4().to_string()).collect();
+    if !active_flows.start(
+        &flow_ids,
+        config.max_active_flows(),
+        config.active_flow_ttl(),
+    ) {
+        return Err(Error::TooManyActiveFlows(active_flows.count()));
+    }
+    Ok(flow_ids)
+}
+
+/// Validate and admit a single new flow, and build its response. Invalid
+/// requests don't use up the rate limit.
+#[allow(clippy::too_many_arguments)]
+fn start_flow(
+    config: &Config,
+    metrics: &Metrics,
+    limiter: &StartLimiter,
+    active_flows: &ActiveFlows,
+    payloads: &PayloadStore,
+    server_url: &ServerUrl,
+    request: StartAuthRequest,
+    options: FlowOptions,
+    verbose: bool,
+) -> Result<TestAuthResponse, Error> {
+    validate_start(config, &request, &options)?;
+    let flow_id = admit_flows(config, limiter, active_flows, 1)?.remove(0);
+    start_response(
+        config, metrics, payloads, server_url, flow_id, request, options, verbose,
+    )
+}
+
+/// Build the url the user should be sent to for a new authentication flow,
+/// which has been validated and admitted already.
+#[allow(clippy::too_many_arguments)]
+fn start_response(
+    config: &Config,
+    metrics: &Metrics,
+    payloads: &PayloadStore,
+    server_url: &ServerUrl,
+    flow_id: String,
+    request: StartAuthRequest,
+    mut options: FlowOptions,
+    verbose: bool,
+) -> Result<TestAuthResponse, Error> {
+    metrics.flows_started.inc();
+
+    info!(flow_id = flow_id.as_str(), "Starting authentication flow");
+    options.flow = Some(flow_id.clone());
+    if config.replay_protection_ttl().is_some() {
+        options.nonce = Some(Uuid::new_v4().simple().to_string());
+    }
+
+    // Short urls refer to payloads kept in the payload store, instead of
+    // carrying them
+    let encode = |payload: Vec<u8>| match config.short_urls_ttl() {
+        Some(ttl) => payloads.store(payload, ttl),
+        None => base64::encode_config(payload, config.url_base64()),
+    };
+    let attributes = encode(serde_json::to_vec(&request.attributes)?);
+    let continuation = encode(request.continuation.into_bytes());
+    // A single attr_url is carried as is, several as a JSON list
+    let mut attr_urls: Vec<String> = request.attr_url.into_iter().collect();
+    attr_urls.append(&mut options.attr_urls);
+    let attr_url = match attr_urls.len() {
+        0 => None,
+        1 => Some(encode(attr_urls.remove(0).into_bytes())),
+        _ => Some(encode(serde_json::to_vec(&attr_urls)?)),
+    };
+
+    let client_url = match &attr_url {
+        Some(attr_url) => format!(
+            "{}/confirm/{}/{}/{}{}",
+            server_url.as_str(),
+            attributes,
+            continuation,
+            attr_url,
+            options.query_string(),
+        ),
+        None => format!(
+            "{}/confirm/{}/{}{}",
+            server_url.as_str(),
+            attributes,
+            continuation,
+            options.query_string(),
+        ),
+    };
+
+    Ok(TestAuthResponse {
+        response: StartAuthResponse { client_url },
+        flow_id,
+        components: if verbose {
+            Some(UrlComponents {
+                server_url: server_url.as_str().to_string(),
+                attributes,
+                continuation,
+                attr_url,
+            })
+        } else {
+            None
+        },
+    })
+}
+
+/// Start an authentication flow. With `dry_run`, the request is only
+/// validated, as a cheap check for integrators. Retries of a request with
+/// the same `Idempotency-Key` header get the response of the original.
+#[post("/start_authentication?<verbose>&<dry_run>", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+async fn start_authentication(
+    config: ConfigSnapshot,
+    metrics: &State<Metrics>,
+    limiter: &State<StartLimiter>,
+    active_flows: &State<ActiveFlows>,
+    payloads: &State<PayloadStore>,
+    server_url: ServerUrl,
+    idempotency_key: IdempotencyKey,
+    idempotency: &State<IdempotencyStore>,
+    request: Json<TestAuthRequest>,
+    verbose: Option<bool>,
+    dry_run: Option<bool>,
+) -> Result<StartResponse, Error> {
+    let request = request.into_inner();
+    if dry_run.unwrap_or(false) {
+        validate_start(&config, &request.request, &request.options)?;
+        return Ok(StartResponse::Validated(Json(
+            serde_json::json!({ "valid": true }),
+        )));
+    }
+
+    let fingerprint = request.fingerprint();
+    let TestAuthRequest { request, options } = request;
+    let start = || {
+        start_flow(
+            &config,
+            metrics,
+            limiter,
+            active_flows,
+            payloads,
+            &server_url,
+            request,
+            options,
+            verbose.unwrap_or(false),
+        )
+    };
+    match idempotency_key.0 {
+        Some(key) => {
+            let response =
+                idempotency.get_or_start(&key, fingerprint, config.idempotency_ttl(), || {
+                    Ok::<_, Error>(serde_json::to_value(start()?)?)
+                })?;
+            Ok(StartResponse::Idempotent(Json(response)))
+        }
+        None => Ok(StartResponse::Started(Json(start()?))),
+    }
+}
+
+/// Start a flow for each of the requests, in order, for generating test
+/// fixtures. All requests are validated and admitted before any flow is
+/// started, an invalid one or one over the limits fails the whole batch.
+#[post("/start_authentication/batch", data = "<requests>")]
+async fn start_authentication_batch(
+    config: ConfigSnapshot,
+    metrics: &State<Metrics>,
+    limiter: &State<StartLimiter>,
+    active_flows: &State<ActiveFlows>,
+    payloads: &State<PayloadStore>,
+    server_url: ServerUrl,
+    requests: Json<Vec<TestAuthRequest>>,
+) -> Result<Json<Vec<TestAuthResponse>>, Error> {
+    let requests = requests.into_inner();
+    if requests.len() > config.max_batch_size() {
+        return Err(Error::BatchTooLarge(
+            requests.len(),
+            config.max_batch_size(),
+        ));
+    }
+    for (index, TestAuthRequest { request, options }) in requests.iter().enumerate() {
+        validate_start(&config, request, options)
+            .map_err(|e| Error::BatchEntry(index, Box::new(e)))?;
+    }
+
+    let flow_ids = admit_flows(&config, limiter, active_flows, requests.len())?;
+
+    let mut responses = Vec::with_capacity(requests.len());
+    let started = requests.into_iter().zip(flow_ids.iter().cloned());
+    for (index, (TestAuthRequest { request, options }, flow_id)) in started.enumerate() {
+        match start_response(
+            &config,
+            metrics,
+            payloads,
+            &server_url,
+            flow_id,
+            request,
+            options,
+            false,
+        ) {
+            Ok(response) => responses.push(response),
+            Err(e) => {
+                // Nobody gets the urls of the flows started so far
+                for flow_id in &flow_ids {
+                    active_flows.complete(flow_id);
+                }
+                return Err(Error::BatchEntry(index, Box::new(e)));
+            }
+        }
+    }
+    Ok(Json(responses))
+}
+
+/// Start an authentication flow from the address bar, for manual testing.
+/// Attributes are given by repeating the `attributes` parameter. Only
+/// available when enabled in the configuration.
+#[get("/start_authentication?<attributes>&<continuation>&<attr_url>&<verbose>&<options..>")]
+#[allow(clippy::too_many_arguments)]
+async fn start_authentication_get(
+    config: ConfigSnapshot,
+    metrics: &State<Metrics>,
+    limiter: &State<StartLimiter>,
+    active_flows: &State<ActiveFlows>,
+    payloads: &State<PayloadStore>,
+    server_url: ServerUrl,
+    attributes: Vec<String>,
+    continuation: String,
+    attr_url: Option<String>,
+    verbose: Option<bool>,
+    options: FlowOptions,
+) -> Result<Option<Json<TestAuthResponse>>, Error> {
+    if !config.allow_get_start() {
+        return Ok(None);
+    }
+
+    let request = StartAuthRequest {
+        attributes,
+        continuation,
+        attr_url,
+    };
+    Ok(Some(Json(start_flow(
+        &config,
+        metrics,
+        limiter,
+        active_flows,
+        payloads,
+        &server_url,
+        request,
+        options,
+        verbose.unwrap_or(false),
+    )?)))
+}
+
+/// The public parts of the default keys, for relying parties to configure
+/// their verification and encryption from. Not available with
+/// deterministic tokens.
+#[get("/.well-known/jwks.json")]
+async fn jwks(config: ConfigSnapshot) -> Option<Json<serde_json::Value>> {
+    config.jwks().cloned().map(Json)
+}
+
+/// Hand-maintained OpenAPI document describing the routes for clients.
+#[get("/openapi.json")]
+async fn openapi() -> RawJson<&'static str> {
+    RawJson(include_str!("openapi.json"))
+}
+
+#[get("/metrics")]
+async fn get_metrics(metrics: &State<Metrics>) -> Result<String, Error> {
+    Ok(metrics.render()?)
+}
+
+#[get("/health")]
+async fn health(config: Option<ConfigSnapshot>) -> Custom<Json<serde_json::Value>> {
+    // Producing a token for an empty result verifies the key material
+    // is actually usable, not just present.
+    let ready = config
+        .map(|config| {
+            let probe = AuthResult {
+                status: AuthStatus::Success,
+                attributes: None,
+                session_url: None,
+            };
+            token::issue(&config, None, None, &probe, false).is_ok()
+        })
+        .unwrap_or(false);
+
+    if ready {
+        Custom(Status::Ok, Json(serde_json::json!({ "status": "ok" })))
+    } else {
+        Custom(
+            Status::ServiceUnavailable,
+            Json(serde_json::json!({ "status": "unavailable" })),
+        )
+    }
+}
+
+/// Shapes of the browser urls, to explain requests that don't match any of
+/// them.
+const BROWSER_PATHS: &[&str] = &[
+    "GET /browser/<attributes>/<continuation>",
+    "POST /browser/<attributes>/<continuation>",
+    "GET /browser/<attributes>/<continuation>/cancel",
+    "GET /browser/<attributes>/<continuation>/<attr_url>",
+    "POST /browser/<attributes>/<continuation>/<attr_url>",
+    "GET /browser/<attributes>/<continuation>/<attr_url>/cancel",
+];
+
+fn browser_path_error(status: Status, request: &Request<'_>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "error": "browser_path",
+        "message": format!(
+            "{} {} does not match any browser url, use the client_url as returned by start_authentication",
+            request.method(),
+            request.uri().path()
+        ),
+        "status": status.code,
+        "expected": BROWSER_PATHS,
+    }))
+}
+
+#[catch(404)]
+fn browser_not_found(request: &Request<'_>) -> Json<serde_json::Value> {
+    browser_path_error(Status::NotFound, request)
+}
+
+#[catch(405)]
+fn browser_method_not_allowed(request: &Request<'_>) -> Json<serde_json::Value> {
+    browser_path_error(Status::MethodNotAllowed, request)
+}
+
+/// Build the provider for a configuration. Settings of Rocket itself, like
+/// the address and port, are loaded by Rocket as usual. TLS and the request
+/// body limit are set from the configuration.
+///
+/// The admin reload endpoint re-reads the configuration from
+/// `rocket::Config::figment()`, so a configuration extracted from another
+/// figment is replaced by the one Rocket loads. Use
+/// [`build_rocket_from_figment`] to have it reload from that figment.
+pub fn build_rocket(config: Config) -> Rocket<Build> {
+    build(config, rocket::Config::figment())
+}
+
+/// Build the provider from a figment holding both its configuration and the
+/// settings of Rocket itself. Reloading the configuration extracts it from
+/// this figment again.
+pub fn build_rocket_from_figment(figment: Figment) -> Result<Rocket<Build>, figment::Error> {
+    let config = figment.extract::<Config>()?;
+    Ok(build(config, figment))
+}
+
+fn build(config: Config, figment: Figment) -> Rocket<Build> {
+    let source = ConfigSource(figment.clone());
+    // TLS is configured through our own settings, so it can be validated
+    // along with the rest of the configuration
+    let figment = match config.tls_paths() {
+        Some((cert_path, key_path)) => {
+            if !config.server_url().starts_with("https://") {
+                warn!("TLS is enabled, but server_url does not use https");
+            }
+            figment
+                .merge(("tls.certs", cert_path))
+                .merge(("tls.key", key_path))
+        }
+        None => figment,
+    };
+    // Applies to the JSON body of start requests, larger bodies get a 413
+    let figment = figment.merge(("limits.json", config.max_request_body_bytes()));
+
+    let base = rocket::custom(figment).mount(
+        "/",
+        routes![
+            cancel_oob,
+            admin::activate_signing_key,
+            admin::active_flows,
+            admin::clear_attr_sink_response,
+            admin::expire_session,
+            admin::inspect,
+            admin::list_sessions,
+            admin::reload,
+            admin::reset,
+            admin::set_attr_sink_response,
+            admin::show_config,
+            attr_sink::last,
+            attr_sink::receive,
+            attr_sink::receive_put,
+            confirm_ib,
+            confirm_oob,
+            health,
+            jwks,
+            get_metrics,
+            openapi,
+            session_history,
+            session_status,
+            session_update,
+            session_update_id,
+            start_authentication,
+            start_authentication_batch,
+            start_authentication_get,
+            user_inline,
+            user_inline_cancel,
+            user_inline_submit,
+            user_oob,
+            user_oob_cancel,
+            user_oob_submit,
+        ],
+    );
+
+    if let Ok(config::TokenKeys::Deterministic) = config.tokens(None) {
+        warn!("Deterministic tokens are enabled, results are neither signed nor encrypted");
+    }
+
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(16)
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_else(|e| panic!("Failure to build http client: {:?}", e));
+
+    let metrics = Metrics::new().unwrap_or_else(|e| panic!("Failure to register metrics: {:?}", e));
+
+    let sessions = SessionStore::default();
+    let chaos = Chaos::new(config.random_seed());
+    let session_ttl = config.session_ttl_seconds();
+    let cors = config.cors().cloned();
+    let security_headers = config.security_headers().cloned();
+    let request_log_level = config.request_log_level();
+    let shutdown_grace = Duration::from_secs(config.shutdown_grace_seconds());
+    let pending_posts = PendingPosts::default();
+    let queue = DeliveryQueue::new(config.attr_url_concurrency());
+
+    let mut server = base
+        .register(
+            "/browser",
+            catchers![browser_not_found, browser_method_not_allowed],
+        )
+        .manage(SharedConfig::new(config))
+        .manage(source)
+        .manage(client)
+        .manage(metrics)
+        .manage(StartLimiter::default())
+        .manage(PayloadStore::default())
+        .manage(NonceStore::default())
+        .manage(ActiveFlows::default())
+        .manage(IdempotencyStore::default())
+        .manage(AttrSink::default())
+        .manage(StepUpStore::default())
+        .manage(chaos)
+        .manage(sessions.clone())
+        .manage(pending_posts.clone())
+        .manage(queue)
+        .attach(AdHoc::on_shutdown("Drain attr_url posts", move |_| {
+            Box::pin(async move {
+                let remaining = pending_posts.drain(shutdown_grace).await;
+                if remaining > 0 {
+                    warn!(
+                        remaining,
+                        "Shutting down with {} attr_url post(s) in progress", remaining
+                    );
+                }
+            })
+        }));
+
+    if let Some(cors) = cors {
+        server = server.attach(Cors::new(cors)).mount(
+            "/",
+            routes![
+                cors::preflight_start,
+                cors::preflight_start_batch,
+                cors::preflight_session
+            ],
+        );
+    }
+
+    if let Some(security_headers) = security_headers {
+        server = server.attach(SecurityHeaders::new(security_headers));
+    }
+
+    if let Some(level) = request_log_level {
+        server = server.attach(RequestLog::new(level));
+    }
+
+    if let Some(ttl) = session_ttl {
+        server = server.attach(AdHoc::on_liftoff("Session expiry", move |_| {
+            Box::pin(async move {
+                rocket::tokio::spawn(async move {
+                    let mut interval = rocket::tokio::time::interval(Duration::from_secs(1));
+                    loop {
+                        interval.tick().await;
+                        sessions.expire_idle(ttl);
+                    }
+                });
+            })
+        }));
+    }
+
+    server
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Instant};
+
+    use rocket::{
+        http::{Header, Status},
+        local::asynchronous::{Client, LocalResponse},
+        tokio::{
+            io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+            net::TcpListener,
+            sync::oneshot,
+            time::timeout,
+        },
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::config::tests::{config, figment, KeyPair, TestKeys};
+
+    /// The server_url of the base test configuration.
+    const SERVER_URL: &str = "https://auth-test.example.com";
+
+    async fn client(layers: &[&str]) -> Client {
+        let figment = rocket::Config::figment().merge(figment(layers));
+        let rocket = build_rocket_from_figment(figment)
+            .unwrap_or_else(|e| panic!("Invalid test configuration: {}", e));
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    /// Start a flow, returning the response.
+    async fn start(client: &Client, request: Value) -> Value {
+        let response = client
+            .post("/start_authentication")
+            .json(&request)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json().await.unwrap()
+    }
+
+    /// Start a flow that is expected to be refused, returning the error body.
+    async fn refused_start(client: &Client, request: Value, status: Status) -> Value {
+        let response = client
+            .post("/start_authentication")
+            .json(&request)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), status);
+        response.into_json().await.unwrap()
+    }
+
+    /// The path of the browser url of a started flow, skipping the
+    /// confirmation page.
+    fn browser_path(started: &Value) -> String {
+        let client_url = started["client_url"].as_str().unwrap();
+        client_url
+            .strip_prefix(SERVER_URL)
+            .unwrap()
+            .replacen("/confirm/", "/browser/", 1)
+    }
+
+    fn location(response: &LocalResponse<'_>) -> String {
+        response.headers().get_one("Location").unwrap().to_string()
+    }
+
+    /// An attr_url that accepts connections, but never responds.
+    async fn unresponsive_attr_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let attr_url = format!("http://{}/result", listener.local_addr().unwrap());
+        rocket::tokio::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+        attr_url
+    }
+
+    /// Complete a flow in the browser, returning the claims of the result it
+    /// redirects to the continuation with.
+    async fn flow_result(client: &Client, request: Value) -> Value {
+        let started = start(client, request).await;
+        let path = browser_path(&started);
+        let response = client.get(path.as_str()).dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+        query_result(&location(&response), "result")
+    }
+
+    /// A request received by an `attr_url_sink`.
+    struct Received {
+        method: String,
+        /// With lowercase names
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    impl Received {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    /// An attr_url that acknowledges the first request made to it, and
+    /// hands that request over.
+    async fn attr_url_sink() -> (String, oneshot::Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let attr_url = format!("http://{}/result", listener.local_addr().unwrap());
+        let (sender, receiver) = oneshot::channel();
+        rocket::tokio::spawn(async move {
+            let (connection, _) = listener.accept().await.unwrap();
+            let mut connection = BufReader::new(connection);
+            let mut line = String::new();
+            connection.read_line(&mut line).await.unwrap();
+            let method = line.split(' ').next().unwrap().to_string();
+            let mut headers = vec![];
+            loop {
+                line.clear();
+                connection.read_line(&mut line).await.unwrap();
+                match line.trim_end().split_once(": ") {
+                    Some((name, value)) => {
+                        headers.push((name.to_ascii_lowercase(), value.to_string()))
+                    }
+                    None => break,
+                }
+            }
+            let length = headers
+                .iter()
+                .find(|(name, _)| name == "content-length")
+                .map_or(0, |(_, length)| length.parse().unwrap());
+            let mut body = vec![0; length];
+            connection.read_exact(&mut body).await.unwrap();
+            connection
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let _ = sender.send(Received {
+                method,
+                headers,
+                body: String::from_utf8(body).unwrap(),
+            });
+        });
+        (attr_url, receiver)
+    }
+
+    /// The claims of a deterministic token.
+    fn claims(token: &str) -> Value {
+        let claims = token
+            .strip_prefix(token::DETERMINISTIC_TOKEN_PREFIX)
+            .unwrap();
+        serde_json::from_str(claims).unwrap()
+    }
+
+    /// The claims of the result in the query of a redirect.
+    fn query_result(location: &str, parameter: &str) -> Value {
+        let url = Url::parse(location).unwrap();
+        let (_, token) = url
+            .query_pairs()
+            .find(|(name, _)| name == parameter)
+            .unwrap();
+        claims(&token)
+    }
+
+    #[rocket::async_test]
+    async fn names_the_result_parameter() {
+        let client = client(&[r#"result_parameter_name = "token""#]).await;
+
+        for (continuation, separator) in [
+            ("https://rp.example.com/done", '?'),
+            ("https://rp.example.com/done?state=1", '&'),
+        ] {
+            let started = start(
+                &client,
+                json!({ "attributes": ["email"], "continuation": continuation }),
+            )
+            .await;
+            let path = browser_path(&started);
+            let response = client.get(path.as_str()).dispatch().await;
+
+            assert_eq!(response.status(), Status::SeeOther);
+            let location = location(&response);
+            assert!(location.starts_with(&format!("{}{}token=", continuation, separator)));
+            let claims = query_result(&location, "token");
+            assert_eq!(claims["attributes"]["email"], "user@example.com");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn gives_up_on_an_unresponsive_attr_url() {
+        let client = client(&["attr_url_timeout_ms = 200"]).await;
+        let started = start(
+            &client,
+            json!({
+                "attributes": ["email"],
+                "continuation": "https://rp.example.com/done",
+                "attr_url": unresponsive_attr_url().await,
+            }),
+        )
+        .await;
+        let path = browser_path(&started);
+
+        let started_at = Instant::now();
+        let response = timeout(Duration::from_secs(5), client.get(path.as_str()).dispatch())
+            .await
+            .expect("the post to the attr_url should time out");
+        assert!(started_at.elapsed() >= Duration::from_millis(200));
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(location(&response), "https://rp.example.com/done");
+    }
+
+    #[rocket::async_test]
+    async fn lists_unknown_attributes() {
+        let client = client(&[]).await;
+        let request = json!({
+            "attributes": ["name", "email", "phone"],
+            "continuation": "https://rp.example.com/done",
+        });
+
+        let error = refused_start(&client, request, Status::BadRequest).await;
+        assert_eq!(error["error"], "unknown_attributes");
+        assert_eq!(error["attributes"], json!(["name", "phone"]));
+    }
+
+    #[test]
+    fn decodes_segments_in_either_base64_alphabet() {
+        let bytes = [0xfb, 0xff];
+        for config in [
+            URL_SAFE_NO_PAD,
+            base64::URL_SAFE,
+            base64::STANDARD_NO_PAD,
+            base64::STANDARD,
+        ] {
+            let segment = base64::encode_config(bytes, config);
+            assert_eq!(decode_segment(&segment).unwrap(), bytes, "{}", segment);
+        }
+        assert!(decode_segment("not base64!").is_err());
+    }
+
+    #[rocket::async_test]
+    async fn throttles_starts_over_the_rate() {
+        let client = client(&["max_starts_per_minute = 2"]).await;
+        let request = json!({
+            "attributes": ["email"],
+            "continuation": "https://rp.example.com/done",
+        });
+        start(&client, request.clone()).await;
+        start(&client, request.clone()).await;
+
+        let response = client
+            .post("/start_authentication")
+            .json(&request)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        let retry_after: u64 = response
+            .headers()
+            .get_one("Retry-After")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after >= 1);
+        let error: Value = response.into_json().await.unwrap();
+        assert_eq!(error["error"], "rate_limited");
+    }
+
+    #[rocket::async_test]
+    async fn requires_attributes_unless_allowed() {
+        let request = json!({
+            "attributes": [],
+            "continuation": "https://rp.example.com/done",
+        });
+
+        let strict = client(&[]).await;
+        let error = refused_start(&strict, request.clone(), Status::BadRequest).await;
+        assert_eq!(error["error"], "no_attributes");
+
+        let lenient = client(&["allow_empty_attributes = true"]).await;
+        start(&lenient, request).await;
+    }
+
+    #[rocket::async_test]
+    async fn posts_the_result_in_a_form() {
+        let client = client(&[r#"
+            delivery_mode = "post"
+            post_field_name = "SAMLResponse"
+            post_form_method = "get"
+        "#])
+        .await;
+        let started = start(
+            &client,
+            json!({
+                "attributes": ["email"],
+                "continuation": "https://rp.example.com/done?state=1&step=2",
+            }),
+        )
+        .await;
+        let path = browser_path(&started);
+        let response = client.get(path.as_str()).dispatch().await;
+
+        assert_eq!(response.status(), Status::Ok);
+        let page = response.into_string().await.unwrap();
+        assert!(page.contains(r#"action="https:"#));
+        assert!(page.contains("state=1&amp;step=2"));
+        assert!(page.contains(r#"method="get""#));
+        assert!(page.contains(r#"name="SAMLResponse""#));
+        // The deterministic token is JSON, its quotes must not end the value
+        assert!(page.contains("auth-test-token:{&quot;"));
+    }
+
+    #[rocket::async_test]
+    async fn delivers_the_result_in_the_fragment() {
+        let client = client(&[r#"delivery_mode = "fragment""#]).await;
+
+        for (continuation, separator) in [
+            ("https://rp.example.com/done", '#'),
+            ("https://rp.example.com/done#state=1", '&'),
+        ] {
+            let started = start(
+                &client,
+                json!({ "attributes": ["email"], "continuation": continuation }),
+            )
+            .await;
+            let path = browser_path(&started);
+            let response = client.get(path.as_str()).dispatch().await;
+
+            assert_eq!(response.status(), Status::SeeOther);
+            let location = location(&response);
+            assert!(location.starts_with(&format!("{}{}result=", continuation, separator)));
+            let url = Url::parse(&location).unwrap();
+            let fragment = url.fragment().unwrap().as_bytes();
+            let (_, token) = url::form_urlencoded::parse(fragment)
+                .find(|(name, _)| name == "result")
+                .unwrap();
+            assert_eq!(claims(&token)["attributes"]["email"], "user@example.com");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn posts_plain_json_to_the_attr_url() {
+        let client = client(&[r#"attr_url_format = "json""#]).await;
+        let (attr_url, received) = attr_url_sink().await;
+        let started = start(
+            &client,
+            json!({
+                "attributes": ["email"],
+                "continuation": "https://rp.example.com/done",
+                "attr_url": attr_url,
+            }),
+        )
+        .await;
+        let path = browser_path(&started);
+        let response = client.get(path.as_str()).dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let received = received.await.unwrap();
+        assert_eq!(received.method, "POST");
+        assert_eq!(received.header("content-type"), Some("application/json"));
+        let result: AuthResult = serde_json::from_str(&received.body).unwrap();
+        assert_eq!(result.attributes.unwrap()["email"], "user@example.com");
+    }
+
+    /// Start a batch of `count` flows, returning the response.
+    async fn start_batch(client: &Client, count: usize) -> LocalResponse<'_> {
+        let request = json!({
+            "attributes": ["email"],
+            "continuation": "https://rp.example.com/done",
+        });
+        client
+            .post("/start_authentication/batch")
+            .json(&vec![request; count])
+            .dispatch()
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn starts_no_flows_of_a_batch_over_the_limits() {
+        let capped = client(&["max_active_flows = 2"]).await;
+        let response = start_batch(&capped, 3).await;
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        let error: Value = response.into_json().await.unwrap();
+        assert_eq!(error["error"], "too_many_active_flows");
+        assert_eq!(start_batch(&capped, 2).await.status(), Status::Ok);
+        assert_eq!(
+            start_batch(&capped, 1).await.status(),
+            Status::ServiceUnavailable
+        );
+
+        let limited = client(&["max_starts_per_minute = 2"]).await;
+        let response = start_batch(&limited, 3).await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        let started: Value = start_batch(&limited, 2).await.into_json().await.unwrap();
+        assert_eq!(started.as_array().unwrap().len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_invalid_starts_without_using_the_rate_limit() {
+        let client = client(&["max_starts_per_minute = 1"]).await;
+        let invalid = json!({ "attributes": [], "continuation": "https://rp.example.com/done" });
+        let error = refused_start(&client, invalid, Status::BadRequest).await;
+        assert_eq!(error["error"], "no_attributes");
+
+        start(
+            &client,
+            json!({ "attributes": ["email"], "continuation": "https://rp.example.com/done" }),
+        )
+        .await;
+    }
+
+    #[test]
+    fn accepts_comma_separated_attributes_when_lenient() {
+        let payloads = PayloadStore::default();
+        let json = base64::encode_config(r#"["email","name"]"#, URL_SAFE_NO_PAD);
+        let list = base64::encode_config("email, name,", URL_SAFE_NO_PAD);
+
+        let strict = config(&[]);
+        assert_eq!(
+            decode_attributes(&strict, &payloads, &json).unwrap(),
+            ["email", "name"]
+        );
+        assert!(matches!(
+            decode_attributes(&strict, &payloads, &list),
+            Err(Error::Segment("attributes", _))
+        ));
+
+        let lenient = config(&["lenient_attribute_parsing = true"]);
+        for segment in [&json, &list] {
+            assert_eq!(
+                decode_attributes(&lenient, &payloads, segment).unwrap(),
+                ["email", "name"]
+            );
+        }
+    }
+
+    #[rocket::async_test]
+    async fn publishes_the_public_keys() {
+        let deterministic = client(&[]).await;
+        let response = deterministic.get("/.well-known/jwks.json").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let keys = TestKeys::rsa();
+        let client = client(&[&keys.config()]).await;
+        let response = client.get("/.well-known/jwks.json").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let jwks: Value = response.into_json().await.unwrap();
+        let published = jwks["keys"].as_array().unwrap();
+        assert_eq!(published.len(), 2);
+        for (key, (usage, algorithm)) in published
+            .iter()
+            .zip([("sig", "RS256"), ("enc", "RSA-OAEP")])
+        {
+            assert_eq!(key["kty"], "RSA");
+            assert_eq!(key["use"], usage);
+            assert_eq!(key["alg"], algorithm);
+            assert!(key["kid"].as_str().map_or(false, |kid| !kid.is_empty()));
+            assert!(key.get("d").is_none());
+        }
+    }
+
+    #[rocket::async_test]
+    async fn records_unknown_session_activities() {
+        let client = client(&["with_session = true"]).await;
+        let started = start(
+            &client,
+            json!({ "attributes": ["email"], "continuation": "https://rp.example.com/done" }),
+        )
+        .await;
+        let path = browser_path(&started);
+        let response = client.get(path.as_str()).dispatch().await;
+        let claims = query_result(&location(&response), "result");
+        let session_url = claims["session_url"].as_str().unwrap();
+        let update = format!(
+            "{}?type=CustomActivity",
+            session_url.strip_prefix("http://127.0.0.1:8000").unwrap()
+        );
+
+        let response = client.post(update.as_str()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let updated: Value = response.into_json().await.unwrap();
+        assert_eq!(updated["known"], true);
+        assert_eq!(updated["state"], "active");
+        let id = updated["id"].as_str().unwrap();
+        let history = format!("/session/{}/history", id);
+        let response = client.get(history.as_str()).dispatch().await;
+        let history: Value = response.into_json().await.unwrap();
+        assert_eq!(history[0]["activity"], "CustomActivity");
+    }
+
+    #[rocket::async_test]
+    async fn adds_the_configured_security_headers() {
+        let plain = client(&[]).await;
+        let response = plain.get("/health").dispatch().await;
+        assert!(response
+            .headers()
+            .get_one("X-Content-Type-Options")
+            .is_none());
+
+        let secured = client(&[r#"
+            [security_headers]
+            frame_options = ""
+        "#])
+        .await;
+        let response = secured.get("/health").dispatch().await;
+        let headers = response.headers();
+        assert_eq!(headers.get_one("X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(headers.get_one("Referrer-Policy"), Some("no-referrer"));
+        assert!(headers.get_one("Content-Security-Policy").is_some());
+        assert!(headers.get_one("X-Frame-Options").is_none());
+    }
+
+    #[rocket::async_test]
+    async fn substitutes_the_result_into_templated_continuations() {
+        let continuation = "https://rp.example.com/done/{result}/next";
+        let request = json!({ "attributes": ["email"], "continuation": continuation });
+
+        let templating = client(&["continuation_template = true"]).await;
+        let started = start(&templating, request.clone()).await;
+        let path = browser_path(&started);
+        let response = templating.get(path.as_str()).dispatch().await;
+        let templated = location(&response);
+        let token = templated
+            .strip_prefix("https://rp.example.com/done/")
+            .and_then(|rest| rest.strip_suffix("/next"))
+            .unwrap();
+        let token = url::form_urlencoded::parse(token.as_bytes())
+            .next()
+            .map(|(token, _)| token.into_owned())
+            .unwrap();
+        assert_eq!(claims(&token)["attributes"]["email"], "user@example.com");
+
+        let plain = client(&[]).await;
+        let started = start(&plain, request).await;
+        let path = browser_path(&started);
+        let response = plain.get(path.as_str()).dispatch().await;
+        let unchanged = location(&response);
+        assert!(unchanged.starts_with(&format!("{}?result=", continuation)));
+    }
+
+    #[rocket::async_test]
+    async fn names_the_segment_that_failed_to_decode() {
+        let client = client(&[]).await;
+        let encode = |bytes: &[u8]| base64::encode_config(bytes, URL_SAFE_NO_PAD);
+        let attributes = encode(br#"["email"]"#);
+        let continuation = encode(b"https://rp.example.com/done");
+        let invalid_utf8 = encode(&[0xff, 0xfe]);
+
+        for (path, segment) in [
+            (format!("/browser/!!!/{}", continuation), "attributes"),
+            (
+                format!("/browser/{}/{}", invalid_utf8, continuation),
+                "attributes",
+            ),
+            (format!("/browser/{}/!!!", attributes), "continuation"),
+            (
+                format!("/browser/{}/{}", attributes, invalid_utf8),
+                "continuation",
+            ),
+            (
+                format!("/browser/{}/{}/!!!", attributes, continuation),
+                "attr_url",
+            ),
+            (
+                format!("/browser/{}/{}/{}", attributes, continuation, invalid_utf8),
+                "attr_url",
+            ),
+        ] {
+            let response = client.get(path.as_str()).dispatch().await;
+            assert_eq!(response.status(), Status::BadRequest, "{}", path);
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["segment"], segment, "{}", path);
+            let message = error["message"].as_str().unwrap();
+            let expected = format!("Could not decode the {} segment", segment);
+            assert!(message.starts_with(&expected), "{}: {}", path, message);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn pads_url_segments_when_configured() {
+        // 29 bytes, which take padding in base64
+        let continuation = "https://rp.example.com/done?x";
+        let request = json!({ "attributes": ["email"], "continuation": continuation });
+
+        for padding in [false, true] {
+            let client = client(&[&format!("url_base64_padding = {}", padding)]).await;
+            let started = start(&client, request.clone()).await;
+            let path = browser_path(&started);
+            let segments = path.split('?').next().unwrap();
+            assert_eq!(segments.contains('='), padding, "{}", path);
+
+            let response = client.get(path.as_str()).dispatch().await;
+            assert_eq!(response.status(), Status::SeeOther);
+            assert!(location(&response).starts_with(&format!("{}&result=", continuation)));
+        }
+    }
+
+    #[rocket::async_test]
+    async fn ends_step_up_flows_after_the_second_round() {
+        let client = client(&[
+            "step_up = true",
+            "max_active_flows = 1",
+            "attr_url_timeout_ms = 200",
+        ])
+        .await;
+        let request = json!({
+            "attributes": ["email"],
+            "continuation": "https://rp.example.com/done?state=1#section",
+            "attr_url": unresponsive_attr_url().await,
+        });
+        let started = start(&client, request.clone()).await;
+        let path = browser_path(&started);
+
+        let response = client.get(path.as_str()).dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(
+            location(&response),
+            "https://rp.example.com/done?state=1&step_up=required#section"
+        );
+        refused_start(&client, request.clone(), Status::ServiceUnavailable).await;
+
+        let response = client.get(path.as_str()).dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(
+            location(&response),
+            "https://rp.example.com/done?state=1#section"
+        );
+        start(&client, request).await;
+    }
+
+    #[rocket::async_test]
+    async fn posts_with_the_configured_content_type() {
+        let client = client(&[r#"attr_url_content_type = "text/plain""#]).await;
+        let (attr_url, received) = attr_url_sink().await;
+        let started = start(
+            &client,
+            json!({
+                "attributes": ["email"],
+                "continuation": "https://rp.example.com/done",
+                "attr_url": attr_url,
+            }),
+        )
+        .await;
+        let path = browser_path(&started);
+        client.get(path.as_str()).dispatch().await;
+
+        let received = received.await.unwrap();
+        assert_eq!(received.header("content-type"), Some("text/plain"));
+        assert!(received.body.starts_with(token::DETERMINISTIC_TOKEN_PREFIX));
+    }
+
+    #[rocket::async_test]
+    async fn fails_or_omits_missing_attributes() {
+        let request = json!({
+            "attributes": ["email", "phone"],
+            "continuation": "https://rp.example.com/done",
+        });
+
+        let failing = client(&["fail_on_missing_attributes = true"]).await;
+        let claims = flow_result(&failing, request.clone()).await;
+        assert_eq!(
+            claims["status"],
+            serde_json::to_value(AuthStatus::Failed).unwrap()
+        );
+        assert!(claims["attributes"].is_null());
+
+        let omitting = client(&["omit_missing_attributes = true"]).await;
+        let claims = flow_result(&omitting, request).await;
+        assert_eq!(
+            claims["status"],
+            serde_json::to_value(AuthStatus::Success).unwrap()
+        );
+        assert_eq!(claims["attributes"], json!({ "email": "user@example.com" }));
+    }
+
+    #[rocket::async_test]
+    async fn refuses_reusing_an_idempotency_key_for_another_request() {
+        let client = client(&["max_active_flows = 2"]).await;
+        let start_with_key = |request: Value| {
+            client
+                .post("/start_authentication")
+                .header(Header::new("Idempotency-Key", "retry-1"))
+                .json(&request)
+                .dispatch()
+        };
+        let original =
+            json!({ "attributes": ["email"], "continuation": "https://rp.example.com/done" });
+        let other =
+            json!({ "attributes": ["email"], "continuation": "https://rp.example.com/other" });
+
+        let response = start_with_key(original.clone()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let started: Value = response.into_json().await.unwrap();
+
+        let response = start_with_key(other.clone()).await;
+        assert_eq!(response.status(), Status::Conflict);
+        let error: Value = response.into_json().await.unwrap();
+        assert_eq!(error["error"], "idempotency_conflict");
+
+        let response = start_with_key(original).await;
+        let retried: Value = response.into_json().await.unwrap();
+        assert_eq!(retried, started);
+
+        // Only the original started a flow, leaving room for one more
+        start(&client, other.clone()).await;
+        refused_start(&client, other, Status::ServiceUnavailable).await;
+    }
+
+    /// Log output, shared with the subscriber writing it.
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// What is logged while redirecting to the continuation with a token.
+    fn redirect_logs(config: &Config, token: &str) -> String {
+        let buffer = LogBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            redirect_with_result(config, "https://rp.example.com/done", token);
+        });
+        let logs = buffer.0.lock().unwrap().clone();
+        String::from_utf8(logs).unwrap()
+    }
+
+    #[test]
+    fn redacts_tokens_in_logs() {
+        let token = "the-result-token";
+
+        let logs = redirect_logs(&config(&[]), token);
+        assert!(logs.contains("<redacted>"), "{}", logs);
+        assert!(!logs.contains(token), "{}", logs);
+
+        let logs = redirect_logs(&config(&["log_tokens = true"]), token);
+        assert!(logs.contains(token), "{}", logs);
+    }
+
+    #[rocket::async_test]
+    async fn delivers_the_result_to_each_attr_url() {
+        let client = client(&[]).await;
+        let (first_url, first) = attr_url_sink().await;
+        let (second_url, second) = attr_url_sink().await;
+        let started = start(
+            &client,
+            json!({
+                "attributes": ["email"],
+                "continuation": "https://rp.example.com/done",
+                "attr_url": first_url,
+                "attr_urls": [second_url],
+            }),
+        )
+        .await;
+        let path = browser_path(&started);
+        let response = client.get(path.as_str()).dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let first = first.await.unwrap();
+        let second = second.await.unwrap();
+        assert!(first.body.starts_with(token::DETERMINISTIC_TOKEN_PREFIX));
+        assert_eq!(first.body, second.body);
+    }
+
+    #[rocket::async_test]
+    async fn switches_the_active_signing_key() {
+        let keys = TestKeys::rsa();
+        let client = client(&[
+            "admin_token = \"secret\"\nactive_signing_kid = \"first\"",
+            &keys.config(),
+            &KeyPair::rsa().table("signing_keys.first", true),
+            &KeyPair::rsa().table("signing_keys.second", true),
+        ])
+        .await;
+
+        let response = client.get("/.well-known/jwks.json").dispatch().await;
+        let jwks: Value = response.into_json().await.unwrap();
+        let kids: Vec<&str> = jwks["keys"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|key| key["kid"].as_str())
+            .collect();
+        assert!(kids.contains(&"first") && kids.contains(&"second"));
+
+        let response = client
+            .post("/admin/signing_key/second/activate")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .post("/admin/signing_key/second/activate")
+            .header(Header::new("Authorization", "Bearer secret"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let switched: Value = response.into_json().await.unwrap();
+        assert_eq!(switched, json!({ "active": "second", "previous": "first" }));
+    }
+
+    #[rocket::async_test]
+    async fn overrides_with_session_per_flow() {
+        for (configured, requested) in [(false, true), (true, false)] {
+            let client = client(&[&format!("with_session = {}", configured)]).await;
+            let claims = flow_result(
+                &client,
+                json!({
+                    "attributes": ["email"],
+                    "continuation": "https://rp.example.com/done",
+                    "with_session": requested,
+                }),
+            )
+            .await;
+            assert_eq!(claims["session_url"].is_string(), requested);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn fails_flows_at_the_configured_rate() {
+        let client = client(&["failure_rate = 0.5\nrandom_seed = 7"]).await;
+        let failed = serde_json::to_value(AuthStatus::Failed).unwrap();
+        let mut outcomes = vec![];
+        for _ in 0..16 {
+            let claims = flow_result(
+                &client,
+                json!({ "attributes": ["email"], "continuation": "https://rp.example.com/done" }),
+            )
+            .await;
+            outcomes.push(claims["status"] == failed);
+        }
+
+        // Flows fail exactly when the seeded decisions say so
+        let chaos = Chaos::new(Some(7));
+        let decisions: Vec<bool> = (0..16).map(|_| chaos.fail(0.5)).collect();
+        assert_eq!(outcomes, decisions);
+        assert!(outcomes.contains(&true) && outcomes.contains(&false));
+    }
+
+    #[rocket::async_test]
+    async fn localizes_pages_by_accept_language() {
+        let client = client(&[]).await;
+        let started = start(
+            &client,
+            json!({ "attributes": ["email"], "continuation": "https://rp.example.com/done" }),
+        )
+        .await;
+        let client_url = started["client_url"].as_str().unwrap();
+        let confirm = client_url.strip_prefix(SERVER_URL).unwrap();
+
+        for (accepted, lang) in [
+            ("en-GB,en;q=0.9,nl;q=0.8", "en"),
+            ("fr, nl-NL;q=0.5", "nl"),
+            ("fr", "nl"),
+        ] {
+            let response = client
+                .get(confirm)
+                .header(Header::new("Accept-Language", accepted))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            let page = response.into_string().await.unwrap();
+            let expected = format!(r#"<html lang="{}">"#, lang);
+            assert!(page.contains(&expected), "{}", accepted);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn includes_the_requested_attributes() {
+        let client = client(&[r#"
+            include_requested_attributes = true
+            omit_missing_attributes = true
+        "#])
+        .await;
+        let claims = flow_result(
+            &client,
+            json!({
+                "attributes": ["email", "phone"],
+                "continuation": "https://rp.example.com/done",
+            }),
+        )
+        .await;
+        let requested: Vec<String> =
+            serde_json::from_str(claims["attributes"][REQUESTED_ATTRIBUTES].as_str().unwrap())
+                .unwrap();
+        assert_eq!(requested, ["email", "phone"]);
+        assert!(claims["attributes"].get("phone").is_none());
+    }
+
+    #[rocket::async_test]
+    async fn delivers_with_the_configured_method() {
+        let client = client(&[r#"attr_url_method = "PUT""#]).await;
+        let (attr_url, received) = attr_url_sink().await;
+        let started = start(
+            &client,
+            json!({
+                "attributes": ["email"],
+                "continuation": "https://rp.example.com/done",
+                "attr_url": attr_url,
+            }),
+        )
+        .await;
+        let path = browser_path(&started);
+        client.get(path.as_str()).dispatch().await;
+
+        assert_eq!(received.await.unwrap().method, "PUT");
+
+        // The built-in sink accepts results delivered this way too
+        let with_sink = client(&[r#"attr_url_method = "PUT""#, "test_endpoints = true"]).await;
+        let response = with_sink
+            .put("/test/attr_sink")
+            .body("token")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let last = with_sink.get("/test/attr_sink/last").dispatch().await;
+        assert_eq!(last.into_string().await.unwrap(), "token");
+    }
+
+    #[rocket::async_test]
+    async fn delivers_json_serialized_tokens() {
+        let keys = TestKeys::rsa();
+        let client = client(&[&keys.config(), r#"jwe_serialization = "json""#]).await;
+        let (attr_url, received) = attr_url_sink().await;
+        let started = start(
+            &client,
+            json!({
+                "attributes": ["email"],
+                "continuation": "https://rp.example.com/done",
+                "attr_url": attr_url,
+            }),
+        )
+        .await;
+        let path = browser_path(&started);
+        let response = client.get(path.as_str()).dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let received = received.await.unwrap();
+        assert_eq!(
+            received.header("content-type"),
+            Some("application/jose+json")
+        );
+        let token: Value = serde_json::from_str(&received.body).unwrap();
+        assert!(token["ciphertext"].is_string());
+
+        // Only compact tokens fit in the url of a redirect
+        let started = start(
+            &client,
+            json!({ "attributes": ["email"], "continuation": "https://rp.example.com/done" }),
+        )
+        .await;
+        let path = browser_path(&started);
+        let response = client.get(path.as_str()).dispatch().await;
+        let error: Value = response.into_json().await.unwrap();
+        assert_eq!(error["error"], "json_serialization_in_url");
+    }
+
+    #[rocket::async_test]
+    async fn carries_the_issuer_of_a_flow_in_its_url() {
+        let keys = TestKeys::rsa();
+        let client = client(&["encrypt_results = false", &keys.config()]).await;
+        let continuation = "https://rp.example.com/done";
+        let issuer = "https://issuer.example.com/?tenant=a&env=test";
+        let started = start(
+            &client,
+            json!({ "attributes": ["email"], "continuation": continuation, "issuer": issuer }),
+        )
+        .await;
+        let path = browser_path(&started);
+        let response = client.get(path.as_str()).dispatch().await;
+        let location = Url::parse(&location(&response)).unwrap();
+        let (_, token) = location
+            .query_pairs()
+            .find(|(name, _)| name == "result")
+            .unwrap();
+        let verifier = josekit::jws::RS256
+            .verifier_from_pem(&keys.signing.public)
+            .unwrap();
+        let (payload, _) = josekit::jwt::decode_with_verifier(token.as_ref(), &verifier).unwrap();
+        assert_eq!(payload.issuer(), Some(issuer));
+
+        let request = json!({
+            "attributes": ["email"],
+            "continuation": continuation,
+            "issuer": "not a url",
+        });
+        let error = refused_start(&client, request, Status::BadRequest).await;
+        assert_eq!(error["error"], "invalid_issuer");
+    }
+
+    #[rocket::async_test]
+    async fn redirects_with_the_configured_status() {
+        for (code, status) in [
+            (302, Status::Found),
+            (303, Status::SeeOther),
+            (307, Status::TemporaryRedirect),
+        ] {
+            let client = client(&[&format!("redirect_status = {}", code)]).await;
+            let started = start(
+                &client,
+                json!({ "attributes": ["email"], "continuation": "https://rp.example.com/done" }),
+            )
+            .await;
+            let path = browser_path(&started);
+            let response = client.get(path.as_str()).dispatch().await;
+            assert_eq!(response.status(), status);
+            assert!(location(&response).starts_with("https://rp.example.com/done?result="));
+        }
+    }
+}
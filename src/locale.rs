@@ -0,0 +1,124 @@
+use std::{collections::HashMap, convert::Infallible};
+
+use rocket::request::{self, FromRequest, Request};
+use serde::Deserialize;
+
+/// Texts on the pages the user interacts with, by message key. Labels of
+/// attributes are under `attribute.<name>`. Missing messages show their key,
+/// missing labels the name of the attribute.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(transparent)]
+pub struct Messages(HashMap<String, String>);
+
+impl Messages {
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.0.get(key).map(String::as_str).unwrap_or(key)
+    }
+
+    pub fn attribute<'a>(&'a self, name: &'a str) -> &'a str {
+        self.0
+            .get(&format!("attribute.{}", name))
+            .map(String::as_str)
+            .unwrap_or(name)
+    }
+
+    /// Add the messages of `other`, replacing any with the same key.
+    pub fn extend(&mut self, other: Messages) {
+        self.0.extend(other.0);
+    }
+}
+
+/// The message bundles shipped with the provider.
+pub fn builtin() -> Result<HashMap<String, Messages>, serde_json::Error> {
+    Ok(HashMap::from([
+        (
+            "nl".to_string(),
+            serde_json::from_str(include_str!("locales/nl.json"))?,
+        ),
+        (
+            "en".to_string(),
+            serde_json::from_str(include_str!("locales/en.json"))?,
+        ),
+    ]))
+}
+
+/// A selected locale, with its messages.
+pub struct Locale<'a> {
+    pub lang: &'a str,
+    pub messages: &'a Messages,
+}
+
+/// The supported locales, with the one used when the user accepts none of
+/// them.
+#[derive(Debug)]
+pub struct Locales {
+    default: String,
+    bundles: HashMap<String, Messages>,
+}
+
+impl Locales {
+    /// Returns `None` when the default locale has no messages.
+    pub fn new(default: String, bundles: HashMap<String, Messages>) -> Option<Locales> {
+        bundles
+            .contains_key(&default)
+            .then_some(Locales { default, bundles })
+    }
+
+    pub fn default_locale(&self) -> &str {
+        &self.default
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.bundles.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// The most preferred locale that is supported. A tag like `en-GB` also
+    /// matches a locale `en`.
+    pub fn select(&self, accepted: &AcceptLanguage) -> Locale<'_> {
+        accepted
+            .0
+            .iter()
+            .find_map(|tag| {
+                let primary = tag.split('-').next().unwrap_or(tag);
+                self.bundles
+                    .get_key_value(tag.as_str())
+                    .or_else(|| self.bundles.get_key_value(primary))
+            })
+            .map(|(lang, messages)| Locale { lang, messages })
+            .unwrap_or_else(|| Locale {
+                lang: &self.default,
+                messages: &self.bundles[&self.default],
+            })
+    }
+}
+
+/// The languages of the `Accept-Language` header, most preferred first.
+/// Languages with a weight of 0 are not acceptable, so left out.
+pub struct AcceptLanguage(pub Vec<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptLanguage {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Infallible> {
+        let header = request.headers().get_one("Accept-Language").unwrap_or("");
+        let mut languages: Vec<(String, f32)> = header
+            .split(',')
+            .filter_map(|language| {
+                let mut parts = language.split(';');
+                let tag = parts.next()?.trim().to_lowercase();
+                let weight = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+                (!tag.is_empty() && weight > 0.0).then_some((tag, weight))
+            })
+            .collect();
+        // Sorting is stable, so languages of equal weight keep their order
+        languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        request::Outcome::Success(AcceptLanguage(
+            languages.into_iter().map(|(tag, _)| tag).collect(),
+        ))
+    }
+}
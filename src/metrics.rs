@@ -0,0 +1,77 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub flows_started: IntCounter,
+    pub flows_completed: IntCounterVec,
+    pub session_updates: IntCounter,
+    pub attr_url_posts: IntCounterVec,
+    pub attr_url_post_duration: Histogram,
+    pub attr_url_queue_depth: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Metrics, prometheus::Error> {
+        let registry = Registry::new_custom(Some("auth_test".to_string()), None)?;
+
+        let flows_started = IntCounter::new(
+            "flows_started_total",
+            "Number of authentication flows started",
+        )?;
+        let flows_completed = IntCounterVec::new(
+            Opts::new(
+                "flows_completed_total",
+                "Number of authentication flows completed in the browser",
+            ),
+            &["flow", "status"],
+        )?;
+        let session_updates = IntCounter::new(
+            "session_updates_total",
+            "Number of session updates received",
+        )?;
+        let attr_url_posts = IntCounterVec::new(
+            Opts::new(
+                "attr_url_posts_total",
+                "Number of attempts to post a result to an attr_url",
+            ),
+            &["outcome"],
+        )?;
+        let attr_url_post_duration = Histogram::with_opts(HistogramOpts::new(
+            "attr_url_post_duration_seconds",
+            "Duration of posting a result to an attr_url",
+        ))?;
+
+        let attr_url_queue_depth = IntGauge::new(
+            "attr_url_queue_depth",
+            "Number of attr_url deliveries waiting for a slot",
+        )?;
+
+        registry.register(Box::new(flows_started.clone()))?;
+        registry.register(Box::new(flows_completed.clone()))?;
+        registry.register(Box::new(session_updates.clone()))?;
+        registry.register(Box::new(attr_url_posts.clone()))?;
+        registry.register(Box::new(attr_url_post_duration.clone()))?;
+        registry.register(Box::new(attr_url_queue_depth.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            flows_started,
+            flows_completed,
+            session_updates,
+            attr_url_posts,
+            attr_url_post_duration,
+            attr_url_queue_depth,
+        })
+    }
+
+    /// Render all metrics in the prometheus text exposition format.
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Nonces of flows whose result has been handed out, used to reject a
+/// second use of the same browser url. Nonces are forgotten after a while,
+/// like the flows they belong to.
+#[derive(Debug, Default)]
+pub struct NonceStore {
+    consumed: Mutex<HashMap<String, Instant>>,
+}
+
+impl NonceStore {
+    /// Mark a nonce as used, returning whether it was still unused. Expired
+    /// nonces are cleaned up along the way.
+    pub fn consume(&self, nonce: &str, ttl: Duration) -> bool {
+        let now = Instant::now();
+
+        let mut consumed = self.consumed.lock().unwrap_or_else(PoisonError::into_inner);
+        consumed.retain(|_, expires| *expires > now);
+        if consumed.contains_key(nonce) {
+            return false;
+        }
+        consumed.insert(nonce.to_string(), now + ttl);
+        true
+    }
+
+    /// Forget all consumed nonces, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut consumed = self.consumed.lock().unwrap_or_else(PoisonError::into_inner);
+        let count = consumed.len();
+        consumed.clear();
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consumes_each_nonce_once_until_it_expires() {
+        let nonces = NonceStore::default();
+        let ttl = Duration::from_secs(60);
+
+        assert!(nonces.consume("first", ttl));
+        assert!(!nonces.consume("first", ttl));
+        assert!(nonces.consume("second", ttl));
+
+        assert!(nonces.consume("short", Duration::from_millis(1)));
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(nonces.consume("short", ttl));
+    }
+}
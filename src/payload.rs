@@ -0,0 +1,51 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Prefix marking a path segment as a reference to a stored payload, rather
+/// than the base64 encoded payload itself.
+pub const STORED_PREFIX: char = '~';
+
+/// Path segment payloads kept server side, so the urls handed out stay short
+/// even for large attribute sets. Payloads expire after a while, like the
+/// flows they belong to.
+#[derive(Debug, Default)]
+pub struct PayloadStore {
+    payloads: Mutex<HashMap<String, (Vec<u8>, Instant)>>,
+}
+
+impl PayloadStore {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, (Vec<u8>, Instant)>> {
+        self.payloads.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Store a payload, returning the path segment referring to it. Expired
+    /// payloads are cleaned up along the way.
+    pub fn store(&self, payload: Vec<u8>, ttl: Duration) -> String {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let now = Instant::now();
+
+        let mut payloads = self.lock();
+        payloads.retain(|_, (_, expires)| *expires > now);
+        payloads.insert(token.clone(), (payload, now + ttl));
+        format!("{}{}", STORED_PREFIX, token)
+    }
+
+    /// Look up the payload for a token, as long as it has not expired.
+    pub fn get(&self, token: &str) -> Option<Vec<u8>> {
+        match self.lock().get(token) {
+            Some((payload, expires)) if *expires > Instant::now() => Some(payload.clone()),
+            _ => None,
+        }
+    }
+
+    /// Drop all stored payloads, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut payloads = self.lock();
+        let count = payloads.len();
+        payloads.clear();
+        count
+    }
+}
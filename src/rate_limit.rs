@@ -0,0 +1,74 @@
+use std::{
+    num::NonZeroU32,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Token bucket limiting the number of flows started, shared by all
+/// clients. The bucket holds at most a minute worth of starts, and refills
+/// continuously.
+#[derive(Debug, Default)]
+pub struct StartLimiter(Mutex<Option<Bucket>>);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl StartLimiter {
+    /// Take a token from the bucket for each of `count` starts, or return how
+    /// long to wait before that many are available. Either all tokens are
+    /// taken or none. The rate is passed on every call, so it follows changes
+    /// in the configuration.
+    pub fn acquire(&self, max_per_minute: NonZeroU32, count: usize) -> Result<(), Duration> {
+        let capacity = f64::from(max_per_minute.get());
+        let rate = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut bucket = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = bucket.get_or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.last_refill = now;
+
+        let needed = count as f64;
+        if bucket.tokens >= needed {
+            bucket.tokens -= needed;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((needed - bucket.tokens) / rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttles_once_the_bucket_is_empty() {
+        let limiter = StartLimiter::default();
+        let rate = NonZeroU32::new(3).unwrap();
+
+        for _ in 0..3 {
+            assert!(limiter.acquire(rate, 1).is_ok());
+        }
+        let wait = limiter.acquire(rate, 1).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(20));
+    }
+
+    #[test]
+    fn takes_tokens_for_all_starts_or_none() {
+        let limiter = StartLimiter::default();
+        let rate = NonZeroU32::new(3).unwrap();
+
+        assert!(limiter.acquire(rate, 2).is_ok());
+        assert!(limiter.acquire(rate, 2).is_err());
+        assert!(limiter.acquire(rate, 1).is_ok());
+        assert!(limiter.acquire(rate, 1).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Instant;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, trace, warn};
+
+/// Level at which requests are logged.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestLogLevel {
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+}
+
+/// Time at which handling of a request started.
+struct RequestStart(Instant);
+
+/// Logs every request with its method, path, matched route, status and
+/// duration, including those for health checks and metrics.
+pub struct RequestLog {
+    level: RequestLogLevel,
+}
+
+impl RequestLog {
+    pub fn new(level: RequestLogLevel) -> RequestLog {
+        RequestLog { level }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request logging",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        let elapsed_ms = start.0.elapsed().as_millis() as u64;
+        let method = request.method().as_str();
+        let path = request.uri().path().as_str();
+        let route = request.route().map(|route| route.uri.to_string());
+        let status = response.status().code;
+
+        // Tracing levels are fixed per call site
+        macro_rules! log_request {
+            ($log:ident) => {
+                $log!(
+                    method,
+                    path,
+                    route = route.as_deref(),
+                    status,
+                    elapsed_ms,
+                    "{} {} responded with {} in {} ms",
+                    method,
+                    path,
+                    status,
+                    elapsed_ms
+                )
+            };
+        }
+        match self.level {
+            RequestLogLevel::Trace => log_request!(trace),
+            RequestLogLevel::Debug => log_request!(debug),
+            RequestLogLevel::Info => log_request!(info),
+            RequestLogLevel::Warn => log_request!(warn),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    Request, Response,
+};
+use serde::{Deserialize, Serialize};
+
+/// Security headers added to all responses. Headers configured as an empty
+/// string are left out.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SecurityHeadersConfig {
+    #[serde(default = "default_content_type_options")]
+    content_type_options: String,
+    #[serde(default = "default_frame_options")]
+    frame_options: String,
+    #[serde(default = "default_referrer_policy")]
+    referrer_policy: String,
+    #[serde(default = "default_content_security_policy")]
+    content_security_policy: String,
+}
+
+fn default_content_type_options() -> String {
+    "nosniff".to_string()
+}
+
+fn default_frame_options() -> String {
+    "DENY".to_string()
+}
+
+fn default_referrer_policy() -> String {
+    "no-referrer".to_string()
+}
+
+/// Allows the pages to submit forms anywhere, as results are posted to the
+/// continuation. The script hash is that of the handler submitting the form
+/// on the post delivery page.
+fn default_content_security_policy() -> String {
+    "default-src 'self'; script-src 'unsafe-hashes' \
+     'sha256-ePniVEkSivX/c7XWBGafqh8tSpiRrKiqYeqbG7N1TOE='; form-action *; frame-ancestors 'none'"
+        .to_string()
+}
+
+pub struct SecurityHeaders {
+    config: SecurityHeadersConfig,
+}
+
+impl SecurityHeaders {
+    pub fn new(config: SecurityHeadersConfig) -> SecurityHeaders {
+        SecurityHeaders { config }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, _: &'r Request<'_>, response: &mut Response<'r>) {
+        let headers = [
+            ("X-Content-Type-Options", &self.config.content_type_options),
+            ("X-Frame-Options", &self.config.frame_options),
+            ("Referrer-Policy", &self.config.referrer_policy),
+            (
+                "Content-Security-Policy",
+                &self.config.content_security_policy,
+            ),
+        ];
+        for (name, value) in headers {
+            if !value.is_empty() {
+                response.set_header(Header::new(name, value.clone()));
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use rocket::{
+    http::Status,
+    request::{self, FromRequest, Request},
+};
+use url::Url;
+
+use crate::config::SharedConfig;
+
+/// Base of the urls handed out to the user's browser. This is the configured
+/// server url, unless it is configured to follow the host the request was
+/// sent to, in which case the scheme and host come from the request.
+pub struct ServerUrl(String);
+
+impl ServerUrl {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// First value of a forwarding header, proxies append to these when chained.
+fn forwarded<'r>(request: &'r Request<'_>, name: &str) -> Option<&'r str> {
+    request
+        .headers()
+        .get_one(name)
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// The configured server url with the scheme and host of the request. The
+/// path of the configured url is kept, as the provider may be served under a
+/// prefix. Hosts that don't make for a valid url are ignored.
+fn derive(request: &Request<'_>, configured: &str, tls: bool) -> Option<String> {
+    let host =
+        forwarded(request, "X-Forwarded-Host").or_else(|| request.headers().get_one("Host"))?;
+    let scheme = match forwarded(request, "X-Forwarded-Proto") {
+        Some(scheme) => scheme,
+        None if tls => "https",
+        None => "http",
+    };
+    if scheme != "http" && scheme != "https" {
+        return None;
+    }
+
+    // IPv6 hosts come in brackets, which the url parser handles as well
+    let base = Url::parse(&format!("{}://{}", scheme, host)).ok()?;
+    if base.path() != "/" || base.query().is_some() || !base.username().is_empty() {
+        return None;
+    }
+    let path = Url::parse(configured)
+        .map(|url| url.path().trim_end_matches('/').to_string())
+        .unwrap_or_default();
+    Some(format!("{}{}", base.as_str().trim_end_matches('/'), path))
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ServerUrl {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let config = match request.rocket().state::<SharedConfig>() {
+            Some(config) => config.snapshot(),
+            None => return request::Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        let derived = if config.derive_server_url_from_host() {
+            derive(request, config.server_url(), config.tls_paths().is_some())
+        } else {
+            None
+        };
+        request::Outcome::Success(ServerUrl(
+            derived.unwrap_or_else(|| config.server_url().to_string()),
+        ))
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocket::{
+    form::{self, FromFormField, ValueField},
+    http::Status,
+    request::{self, FromRequest, Request},
+};
+use serde::Serialize;
+use tracing::warn;
+use verder_helpen_proto::SessionActivity;
+
+use crate::config::SharedConfig;
+
+/// Names of the activities that end a session. The set of activities differs
+/// between proto versions, so these are matched on name.
+const FINISH_ACTIVITIES: &[&str] = &["Finish", "Close"];
+
+/// Activity of a session update. Activities the proto doesn't know are kept
+/// by name, so tests can send custom activities without failing the update.
+#[derive(Debug)]
+pub enum Activity {
+    Known(SessionActivity),
+    Unknown(String),
+}
+
+impl Activity {
+    pub fn name(&self) -> String {
+        match self {
+            Activity::Known(activity) => format!("{:?}", activity),
+            Activity::Unknown(name) => name.clone(),
+        }
+    }
+
+    pub fn is_known(&self) -> bool {
+        matches!(self, Activity::Known(_))
+    }
+}
+
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for Activity {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        let name = field.value;
+        match SessionActivity::from_value(field) {
+            Ok(activity) => Ok(Activity::Known(activity)),
+            Err(_) => Ok(Activity::Unknown(name.to_string())),
+        }
+    }
+}
+
+/// Guard for session updates, checking they were signed by the broker when a
+/// `session_update_verify_key` is configured. The `X-Signature` header must
+/// then hold a JWS in compact serialization, signed with the private
+/// counterpart of that key, whose payload is the path and query of the
+/// update, like `/session/<id>/update?type=Start`. Updates with a missing or
+/// wrong signature are rejected as unauthorized.
+pub struct SignedUpdate;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SignedUpdate {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let config = match request.rocket().state::<SharedConfig>() {
+            Some(config) => config.snapshot(),
+            None => return request::Outcome::Error((Status::InternalServerError, ())),
+        };
+        let verifier = match config.session_update_verifier() {
+            Some(verifier) => verifier,
+            None => return request::Outcome::Success(SignedUpdate),
+        };
+
+        let signature = match request.headers().get_one("X-Signature") {
+            Some(signature) => signature,
+            None => {
+                warn!("Rejecting unsigned session update");
+                return request::Outcome::Error((Status::Unauthorized, ()));
+            }
+        };
+        match josekit::jws::deserialize_compact(signature, verifier) {
+            Ok((payload, _)) if payload == request.uri().to_string().as_bytes() => {
+                request::Outcome::Success(SignedUpdate)
+            }
+            Ok(_) => {
+                warn!("Rejecting session update, signature is for another request");
+                request::Outcome::Error((Status::Unauthorized, ()))
+            }
+            Err(e) => {
+                warn!("Rejecting session update with invalid signature: {}", e);
+                request::Outcome::Error((Status::Unauthorized, ()))
+            }
+        }
+    }
+}
+
+/// A single session update, as received from the broker.
+#[derive(Serialize, Debug, Clone)]
+pub struct ActivityRecord {
+    pub activity: String,
+    pub timestamp: u64,
+}
+
+#[derive(FromFormField, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionState {
+    Active,
+    Expired,
+    /// Only reported in response to the update finishing the session, as
+    /// finished sessions are not kept.
+    Finished,
+}
+
+#[derive(Debug)]
+pub struct Session {
+    history: Vec<ActivityRecord>,
+    state: SessionState,
+    last_activity: u64,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            history: vec![],
+            state: SessionState::Active,
+            last_activity: now(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionStatus {
+    pub id: String,
+    pub state: SessionState,
+    pub last_activity: u64,
+}
+
+/// Response to a session update.
+#[derive(Serialize, Debug)]
+pub struct SessionUpdateResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub known: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<SessionState>,
+}
+
+/// In-memory record of the sessions handed out by the browser handlers
+#[derive(Debug, Default, Clone)]
+pub struct SessionStore {
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl SessionStore {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Start tracking a new session, returning its id. Sessions of a flow
+    /// get the id of that flow, so they can be correlated with its logs.
+    /// Flows started without an id get a fresh one.
+    pub fn create(&self, flow_id: Option<&str>) -> String {
+        let id = match flow_id {
+            Some(flow_id) => flow_id.to_string(),
+            None => uuid::Uuid::new_v4().to_string(),
+        };
+        self.lock().insert(id.clone(), Session::new());
+        id
+    }
+
+    /// Record an activity for a session, returning the resulting state of
+    /// the session, or `None` if it is unknown. Any activity keeps an active
+    /// session alive, except for the ones finishing it, which remove the
+    /// session altogether.
+    pub fn record(&self, id: &str, activity: String) -> Option<SessionState> {
+        let mut sessions = self.lock();
+        if FINISH_ACTIVITIES.contains(&activity.as_str()) {
+            return sessions.remove(id).map(|_| SessionState::Finished);
+        }
+
+        let session = sessions.get_mut(id)?;
+        let timestamp = now();
+        if session.state == SessionState::Active {
+            session.last_activity = timestamp;
+        }
+        session.history.push(ActivityRecord {
+            activity,
+            timestamp,
+        });
+        Some(session.state)
+    }
+
+    pub fn history(&self, id: &str) -> Option<Vec<ActivityRecord>> {
+        self.lock().get(id).map(|session| session.history.clone())
+    }
+
+    pub fn status(&self, id: &str) -> Option<SessionStatus> {
+        self.lock().get(id).map(|session| SessionStatus {
+            id: id.to_string(),
+            state: session.state,
+            last_activity: session.last_activity,
+        })
+    }
+
+    /// Stop tracking all sessions, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut sessions = self.lock();
+        let count = sessions.len();
+        sessions.clear();
+        count
+    }
+
+    /// Status of all tracked sessions, optionally only those in a given
+    /// state, ordered by their last activity.
+    pub fn list(&self, state: Option<SessionState>) -> Vec<SessionStatus> {
+        let mut sessions: Vec<SessionStatus> = self
+            .lock()
+            .iter()
+            .filter(|(_, session)| state.map_or(true, |state| session.state == state))
+            .map(|(id, session)| SessionStatus {
+                id: id.clone(),
+                state: session.state,
+                last_activity: session.last_activity,
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.last_activity);
+        sessions
+    }
+
+    /// Mark a session as expired right away, returning its status from
+    /// before, or `None` if it is unknown.
+    pub fn expire(&self, id: &str) -> Option<SessionStatus> {
+        let mut sessions = self.lock();
+        let session = sessions.get_mut(id)?;
+        let prior = SessionStatus {
+            id: id.to_string(),
+            state: session.state,
+            last_activity: session.last_activity,
+        };
+        session.state = SessionState::Expired;
+        Some(prior)
+    }
+
+    /// Mark all active sessions without any activity in the last `ttl`
+    /// seconds as expired.
+    pub fn expire_idle(&self, ttl: u64) {
+        let now = now();
+        for session in self.lock().values_mut() {
+            if session.state == SessionState::Active
+                && now.saturating_sub(session.last_activity) > ttl
+            {
+                session.state = SessionState::Expired;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_unknown_activities_by_name() {
+        let activity = Activity::from_value(ValueField::from_value("CustomActivity")).unwrap();
+        assert!(!activity.is_known());
+        assert_eq!(activity.name(), "CustomActivity");
+    }
+}
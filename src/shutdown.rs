@@ -0,0 +1,52 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rocket::tokio::{sync::Notify, time::timeout};
+
+/// Count of the attr_url posts in progress, so shutdown can wait for them to
+/// finish instead of leaving the broker without a result.
+#[derive(Debug, Default, Clone)]
+pub struct PendingPosts {
+    count: Arc<AtomicUsize>,
+    done: Arc<Notify>,
+}
+
+/// Marks a post as in progress for as long as it is held.
+pub struct PendingPost(PendingPosts);
+
+impl PendingPosts {
+    pub fn track(&self) -> PendingPost {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        PendingPost(self.clone())
+    }
+
+    /// Wait until no posts are in progress, for at most `grace`. Returns
+    /// the number of posts still in progress afterwards.
+    pub async fn drain(&self, grace: Duration) -> usize {
+        let wait = async {
+            loop {
+                // Create the notification before checking, so a post
+                // finishing in between is not missed
+                let done = self.done.notified();
+                if self.count.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                done.await;
+            }
+        };
+        let _ = timeout(grace, wait).await;
+        self.count.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for PendingPost {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+        self.0.done.notify_waiters();
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Round of a step-up flow a result is handed out for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// The first browser hit of a flow, which gets a low assurance result.
+    Initial,
+    /// The browser hit re-entering the flow, which gets the full result.
+    Final,
+}
+
+/// Flows midway a step-up, whose low assurance result has been handed out
+/// but whose full result has not. Flows are forgotten after a while, like
+/// nonces.
+#[derive(Debug, Default)]
+pub struct StepUpStore {
+    pending: Mutex<HashMap<String, Instant>>,
+}
+
+impl StepUpStore {
+    /// Move a flow to its next round, returning that round. A flow that
+    /// completed its final round starts over, so the sequence repeats when
+    /// the same url is used again. Expired flows are cleaned up along the
+    /// way.
+    pub fn advance(&self, flow: &str, ttl: Duration) -> Step {
+        let now = Instant::now();
+
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        pending.retain(|_, expires| *expires > now);
+        if pending.remove(flow).is_some() {
+            return Step::Final;
+        }
+        pending.insert(flow.to_string(), now + ttl);
+        Step::Initial
+    }
+
+    /// Forget all flows midway a step-up, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let count = pending.len();
+        pending.clear();
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alternates_between_rounds_per_flow() {
+        let step_ups = StepUpStore::default();
+        let ttl = Duration::from_secs(60);
+
+        assert_eq!(step_ups.advance("first", ttl), Step::Initial);
+        assert_eq!(step_ups.advance("second", ttl), Step::Initial);
+        assert_eq!(step_ups.advance("first", ttl), Step::Final);
+        assert_eq!(step_ups.advance("first", ttl), Step::Initial);
+        assert_eq!(step_ups.advance("second", ttl), Step::Final);
+    }
+
+    #[test]
+    fn starts_over_once_the_second_round_expires() {
+        let step_ups = StepUpStore::default();
+
+        assert_eq!(
+            step_ups.advance("flow", Duration::from_millis(1)),
+            Step::Initial
+        );
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(
+            step_ups.advance("flow", Duration::from_secs(60)),
+            Step::Initial
+        );
+    }
+}
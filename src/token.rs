@@ -0,0 +1,679 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use josekit::{
+    jwe::{self, JweDecrypter, JweEncrypter, JweHeader},
+    jws::{JwsHeader, JwsSigner, JwsVerifier},
+    jwt::{self, JwtPayload},
+};
+use rocket::form::FromFormField;
+use serde::Deserialize;
+use serde_json::Value;
+use verder_helpen_jwt::{decrypt_and_verify_auth_result, sign_and_encrypt_auth_result};
+use verder_helpen_proto::AuthResult;
+
+use crate::{
+    config::{AttributeResultShape, Config, InspectKeys, TokenKeys, TokenSerialization},
+    keys::ContentEncoding,
+    Error,
+};
+
+/// Prefix of the placeholder tokens issued when deterministic tokens are
+/// enabled.
+pub const DETERMINISTIC_TOKEN_PREFIX: &str = "auth-test-token:";
+
+/// The registered claims of a token, except for the audience, which is
+/// implied by its keys.
+struct StandardClaims<'a> {
+    issuer: Option<&'a str>,
+    issued_at: SystemTime,
+    expires_at: SystemTime,
+    not_before: Option<SystemTime>,
+}
+
+impl<'a> StandardClaims<'a> {
+    /// Times following the configured lifetime and skew. Expired tokens are
+    /// issued as if a full lifetime and another minute ago.
+    fn new(config: &Config, issuer: Option<&'a str>, expired: bool) -> StandardClaims<'a> {
+        let now = SystemTime::now();
+        let lifetime = config.token_lifetime();
+        let issued_at = if expired {
+            now.checked_sub(lifetime + Duration::from_secs(60))
+                .unwrap_or(UNIX_EPOCH)
+        } else {
+            now
+        };
+        StandardClaims {
+            issuer,
+            issued_at,
+            expires_at: issued_at + lifetime,
+            not_before: config
+                .token_not_before_skew()
+                .map(|skew| issued_at.checked_sub(skew).unwrap_or(UNIX_EPOCH)),
+        }
+    }
+}
+
+/// Produce the token carrying an auth result, encrypted unless the
+/// configuration asks for signed-only results.
+/// The keys used are those of the audience, if given. The issuer, if given,
+/// replaces the configured one. Expired tokens are meant for testing their
+/// rejection by the relying party.
+pub fn issue(
+    config: &Config,
+    audience: Option<&str>,
+    issuer: Option<&str>,
+    auth_result: &AuthResult,
+    expired: bool,
+) -> Result<String, Error> {
+    let (signer, encrypter) = match config.tokens(audience)? {
+        TokenKeys::Keys { signer, encrypter } => (signer.as_ref(), encrypter.as_ref()),
+        TokenKeys::Deterministic => {
+            return deterministic_token(auth_result, config.attribute_result_shape())
+        }
+    };
+    // Rotated keys replace the default signing key
+    let (kid, signer) = match (audience, config.key_rotation()) {
+        (None, Some(rotation)) => {
+            let (kid, signer) = rotation.active_signer();
+            (Some(kid), signer)
+        }
+        _ => (None, signer),
+    };
+    let kid = kid.as_deref();
+
+    let claims = StandardClaims::new(config, issuer.or_else(|| config.issuer()), expired);
+    let shape = config.attribute_result_shape();
+    if !config.encrypt_results() {
+        return sign_auth_result(auth_result, shape, signer, kid, &claims);
+    }
+
+    // Tokens of verder_helpen_jwt have neither a kid nor an issuer, and their
+    // attributes are an object
+    let plain = kid.is_none() && claims.issuer.is_none() && shape == AttributeResultShape::Object;
+    match config.encryption_encoding() {
+        None if config.default_token_timing() && !expired && plain => Ok(
+            sign_and_encrypt_auth_result(auth_result, signer, encrypter)?,
+        ),
+        encoding => sign_and_encrypt_with_encoding(
+            auth_result,
+            shape,
+            signer,
+            kid,
+            encrypter,
+            encoding,
+            &claims,
+        ),
+    }
+}
+
+/// Deliberate damage to a token, to test how relying parties handle broken
+/// tokens. See `corrupt` for exactly what each one does.
+#[derive(FromFormField, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Corruption {
+    Signature,
+    Ciphertext,
+    Truncated,
+}
+
+impl Corruption {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Corruption::Signature => "signature",
+            Corruption::Ciphertext => "ciphertext",
+            Corruption::Truncated => "truncated",
+        }
+    }
+}
+
+/// Damage a token in one of these reproducible ways:
+/// - `signature` flips the first character of the last part, the signature of a
+///   JWS or the authentication tag of a JWE.
+/// - `ciphertext` flips the first character of the ciphertext of a JWE, or of
+///   the payload of a JWS.
+/// - `truncated` drops the second half of the token.
+///
+/// Flipping replaces the character with `A`, or `A` with `B`. Tokens without
+/// parts, like deterministic tokens, have their first character flipped.
+pub fn corrupt(token: &str, corruption: Corruption) -> String {
+    let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+    let index = match corruption {
+        Corruption::Truncated => {
+            let end = token.char_indices().nth(token.chars().count() / 2);
+            return token[..end.map(|(i, _)| i).unwrap_or(0)].to_string();
+        }
+        Corruption::Signature => parts.len() - 1,
+        Corruption::Ciphertext if parts.len() == 5 => 3,
+        Corruption::Ciphertext => parts.len().min(2) - 1,
+    };
+
+    let part = &mut parts[index];
+    let flipped = match part.chars().next() {
+        Some('A') => 'B',
+        _ => 'A',
+    };
+    let rest = part.chars().skip(1).collect::<String>();
+    *part = format!("{}{}", flipped, rest);
+    parts.join(".")
+}
+
+/// Put a compact token in another serialization. The JSON serialization is
+/// the flattened one, with the members of a JWE, or of a JWS for signed-only
+/// results. Tokens that are neither, like deterministic or truncated tokens,
+/// are left as they are.
+pub fn serialize(token: &str, serialization: TokenSerialization) -> String {
+    if serialization == TokenSerialization::Compact {
+        return token.to_string();
+    }
+    match token.split('.').collect::<Vec<_>>().as_slice() {
+        [protected, encrypted_key, iv, ciphertext, tag] => serde_json::json!({
+            "protected": protected,
+            "encrypted_key": encrypted_key,
+            "iv": iv,
+            "ciphertext": ciphertext,
+            "tag": tag,
+        })
+        .to_string(),
+        [protected, payload, signature] => serde_json::json!({
+            "payload": payload,
+            "protected": protected,
+            "signature": signature,
+        })
+        .to_string(),
+        _ => token.to_string(),
+    }
+}
+
+/// The attributes of a result in the given shape, sorted by name. `null`
+/// when the result has no attributes.
+pub fn attributes_claim(auth_result: &AuthResult, shape: AttributeResultShape) -> Value {
+    let attributes = match &auth_result.attributes {
+        Some(attributes) => attributes.iter().collect::<BTreeMap<_, _>>(),
+        None => return Value::Null,
+    };
+    match shape {
+        AttributeResultShape::Object => serde_json::json!(attributes),
+        AttributeResultShape::Array => attributes
+            .into_iter()
+            .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+            .collect(),
+    }
+}
+
+/// Turn attributes in the array shape back into an object, so the claims
+/// deserialize as an `AuthResult`. Claims with an object are left alone.
+fn normalize_attributes(claims: &mut Value) -> Result<(), Error> {
+    #[derive(Deserialize)]
+    struct Entry {
+        name: String,
+        value: String,
+    }
+
+    let entries = match claims.get_mut("attributes") {
+        Some(Value::Array(entries)) => std::mem::take(entries),
+        _ => return Ok(()),
+    };
+    let mut attributes = serde_json::Map::new();
+    for entry in entries {
+        let Entry { name, value } =
+            serde_json::from_value(entry).map_err(|e| Error::InvalidToken(e.to_string()))?;
+        attributes.insert(name, Value::String(value));
+    }
+    claims["attributes"] = Value::Object(attributes);
+    Ok(())
+}
+
+/// Placeholder for a token, for tests that want to assert on exact results.
+/// It is `auth-test-token:` followed by a JSON object with the `status`,
+/// `attributes` (in the given shape, sorted by name) and `session_url` of the
+/// result. It is neither signed nor encrypted, and the session url still
+/// contains a random session id.
+pub fn deterministic_token(
+    auth_result: &AuthResult,
+    shape: AttributeResultShape,
+) -> Result<String, Error> {
+    let claims = serde_json::json!({
+        "status": auth_result.status,
+        "attributes": attributes_claim(auth_result, shape),
+        "session_url": auth_result.session_url,
+    });
+
+    Ok(format!("{}{}", DETERMINISTIC_TOKEN_PREFIX, claims))
+}
+
+/// Like `sign_and_encrypt_auth_result`, but with a configurable content
+/// encryption, attribute shape, registered claims and kid instead of the ones
+/// fixed by `verder_helpen_jwt`. Without an encoding, josekit picks its
+/// default.
+fn sign_and_encrypt_with_encoding(
+    auth_result: &AuthResult,
+    shape: AttributeResultShape,
+    signer: &dyn JwsSigner,
+    kid: Option<&str>,
+    encrypter: &dyn JweEncrypter,
+    encoding: Option<ContentEncoding>,
+    claims: &StandardClaims<'_>,
+) -> Result<String, Error> {
+    let signed = sign_auth_result(auth_result, shape, signer, kid, claims)?;
+
+    let mut header = JweHeader::new();
+    header.set_token_type("JWT");
+    header.set_content_type("JWT");
+    if let Some(encoding) = encoding {
+        header.set_content_encryption(encoding.as_str());
+    }
+
+    Ok(jwe::serialize_compact(
+        signed.as_bytes(),
+        &header,
+        encrypter,
+    )?)
+}
+
+/// Sign an auth result as a plain JWS, with the same claims as the signed
+/// token nested in the JWE produced by `sign_and_encrypt_auth_result`, apart
+/// from the shape of the attributes.
+fn sign_auth_result(
+    auth_result: &AuthResult,
+    shape: AttributeResultShape,
+    signer: &dyn JwsSigner,
+    kid: Option<&str>,
+    claims: &StandardClaims<'_>,
+) -> Result<String, Error> {
+    let mut header = JwsHeader::new();
+    header.set_token_type("JWT");
+    if let Some(kid) = kid {
+        header.set_key_id(kid);
+    }
+
+    let mut payload = JwtPayload::new();
+    payload.set_claim("status", Some(serde_json::to_value(&auth_result.status)?))?;
+    payload.set_claim("attributes", Some(attributes_claim(auth_result, shape)))?;
+    payload.set_claim(
+        "session_url",
+        Some(serde_json::to_value(&auth_result.session_url)?),
+    )?;
+    if let Some(issuer) = claims.issuer {
+        payload.set_issuer(issuer);
+    }
+    payload.set_issued_at(&claims.issued_at);
+    payload.set_expires_at(&claims.expires_at);
+    if let Some(not_before) = &claims.not_before {
+        payload.set_not_before(not_before);
+    }
+
+    Ok(jwt::encode_with_signer(&payload, &header, signer)?)
+}
+
+/// Turn a token produced with the default keys back into the auth result it
+/// carries, verifying its signature. Returns `None` when the keys needed for
+/// that are not configured.
+pub fn inspect(config: &Config, token: &str) -> Option<Result<AuthResult, Error>> {
+    let token = token.trim();
+    if let Some(claims) = token.strip_prefix(DETERMINISTIC_TOKEN_PREFIX) {
+        return Some(
+            serde_json::from_str(claims)
+                .map_err(|e| Error::InvalidToken(e.to_string()))
+                .and_then(auth_result_from_claims),
+        );
+    }
+
+    let InspectKeys {
+        verifier,
+        decrypter,
+    } = config.inspect_keys()?;
+    // A JWE has five parts in compact serialization, a JWS three.
+    // verder_helpen_jwt only decodes attributes in the object shape.
+    let result = if token.split('.').count() != 5 {
+        verify_auth_result(token, verifier.as_ref())
+    } else if config.attribute_result_shape() == AttributeResultShape::Object {
+        decrypt_and_verify_auth_result(token, verifier.as_ref(), decrypter.as_ref())
+            .map_err(|e| Error::InvalidToken(e.to_string()))
+    } else {
+        decrypt_auth_result(token, verifier.as_ref(), decrypter.as_ref())
+    };
+    Some(result)
+}
+
+/// Counterpart of `sign_and_encrypt_with_encoding`.
+fn decrypt_auth_result(
+    token: &str,
+    verifier: &dyn JwsVerifier,
+    decrypter: &dyn JweDecrypter,
+) -> Result<AuthResult, Error> {
+    let (signed, _) = jwe::deserialize_compact(token, decrypter)
+        .map_err(|e| Error::InvalidToken(e.to_string()))?;
+    let signed = std::str::from_utf8(&signed).map_err(|e| Error::InvalidToken(e.to_string()))?;
+    verify_auth_result(signed, verifier)
+}
+
+/// Counterpart of `sign_auth_result`.
+fn verify_auth_result(token: &str, verifier: &dyn JwsVerifier) -> Result<AuthResult, Error> {
+    let (payload, _) = jwt::decode_with_verifier(token, verifier)
+        .map_err(|e| Error::InvalidToken(e.to_string()))?;
+    auth_result_from_claims(Value::Object(payload.claims_set().clone()))
+}
+
+/// The auth result in the claims of a token, with attributes in either shape.
+fn auth_result_from_claims(mut claims: Value) -> Result<AuthResult, Error> {
+    normalize_attributes(&mut claims)?;
+    serde_json::from_value(claims).map_err(|e| Error::InvalidToken(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use josekit::{
+        jwe::RSA_OAEP,
+        jws::{ES256, PS256, RS256},
+    };
+    use verder_helpen_proto::AuthStatus;
+
+    use super::*;
+    use crate::config::tests::{config, KeyPair, TestKeys};
+
+    /// Decrypt and verify a token of the default keys, as a relying party
+    /// using verder_helpen_jwt would.
+    fn open(token: &str, keys: &TestKeys) -> Option<AuthResult> {
+        let verifier = RS256.verifier_from_pem(&keys.signing.public).unwrap();
+        let decrypter = RSA_OAEP
+            .decrypter_from_pem(&keys.encryption.private)
+            .unwrap();
+        decrypt_and_verify_auth_result(token, &verifier, &decrypter).ok()
+    }
+
+    /// The decoded protected header of a compact JWS or JWE.
+    fn header(token: &str) -> Value {
+        let header = token.split('.').next().unwrap();
+        let header = base64::decode_config(header, base64::URL_SAFE_NO_PAD).unwrap();
+        serde_json::from_slice(&header).unwrap()
+    }
+
+    /// The claims of a signed-only token, after verifying its signature.
+    fn verify(token: &str, key: &KeyPair) -> JwtPayload {
+        let verifier = RS256.verifier_from_pem(&key.public).unwrap();
+        let (payload, _) = jwt::decode_with_verifier(token, &verifier).unwrap();
+        payload
+    }
+
+    /// A successful result with the configured values of the attributes.
+    fn success(config: &Config, attributes: &[&str]) -> AuthResult {
+        let attributes: Vec<String> = attributes.iter().map(|a| a.to_string()).collect();
+        AuthResult {
+            status: AuthStatus::Success,
+            attributes: Some(config.map_attributes(&attributes).unwrap()),
+            session_url: None,
+        }
+    }
+
+    #[test]
+    fn encrypts_multiple_values_as_json_list() {
+        let keys = TestKeys::rsa();
+        let config = config(&[
+            &keys.config(),
+            r#"
+            [attributes]
+            roles = ["user", "tester"]
+            "#,
+        ]);
+        let result = success(&config, &["email", "roles"]);
+
+        let token = issue(&config, None, None, &result, false).unwrap();
+        assert_eq!(token.split('.').count(), 5);
+        let attributes: HashMap<String, String> = open(&token, &keys).unwrap().attributes.unwrap();
+        assert_eq!(attributes["email"], "user@example.com");
+        assert_eq!(attributes["roles"], r#"["user","tester"]"#);
+    }
+
+    #[test]
+    fn signs_without_encrypting() {
+        let keys = TestKeys::rsa();
+        let config = config(&["encrypt_results = false", &keys.config()]);
+        let result = success(&config, &["email"]);
+
+        let token = issue(&config, None, None, &result, false).unwrap();
+        assert_eq!(token.split('.').count(), 3);
+        let payload = verify(&token, &keys.signing);
+        assert_eq!(
+            payload.claim("status"),
+            Some(&serde_json::to_value(AuthStatus::Success).unwrap())
+        );
+        assert_eq!(
+            payload.claim("attributes"),
+            Some(&serde_json::json!({ "email": "user@example.com" }))
+        );
+    }
+
+    #[test]
+    fn signs_with_the_configured_algorithm() {
+        let keys = TestKeys {
+            signing: KeyPair::ec(),
+            encryption: KeyPair::rsa(),
+        };
+        let es256 = config(&["encrypt_results = false", &keys.config()]);
+        let result = success(&es256, &["email"]);
+
+        let token = issue(&es256, None, None, &result, false).unwrap();
+        assert_eq!(header(&token)["alg"], "ES256");
+        let verifier = ES256.verifier_from_pem(&keys.signing.public).unwrap();
+        assert!(jwt::decode_with_verifier(&token, &verifier).is_ok());
+
+        let keys = TestKeys::rsa();
+        let rs256 = config(&["encrypt_results = false", &keys.config()]);
+        let token = issue(&rs256, None, None, &result, false).unwrap();
+        assert_eq!(header(&token)["alg"], "RS256");
+        let verifier = RS256.verifier_from_pem(&keys.signing.public).unwrap();
+        assert!(jwt::decode_with_verifier(&token, &verifier).is_ok());
+
+        let ps256 = config(&[
+            "encrypt_results = false",
+            &keys.config(),
+            "signing_algorithm = \"PS256\"",
+        ]);
+        let token = issue(&ps256, None, None, &result, false).unwrap();
+        assert_eq!(header(&token)["alg"], "PS256");
+        let verifier = PS256.verifier_from_pem(&keys.signing.public).unwrap();
+        assert!(jwt::decode_with_verifier(&token, &verifier).is_ok());
+    }
+
+    #[test]
+    fn encrypts_with_the_configured_algorithm_and_encoding() {
+        let keys = TestKeys::rsa();
+        let config = config(&[
+            &keys.config(),
+            "encryption_algorithm = \"RSA-OAEP-256\"\nencryption_encoding = \"A256GCM\"",
+        ]);
+        let result = success(&config, &["email"]);
+
+        let token = issue(&config, None, None, &result, false).unwrap();
+        let header = header(&token);
+        assert_eq!(header["alg"], "RSA-OAEP-256");
+        assert_eq!(header["enc"], "A256GCM");
+    }
+
+    #[test]
+    fn uses_the_keys_of_the_audience() {
+        let keys = TestKeys::rsa();
+        let first = TestKeys::rsa();
+        let second = TestKeys::rsa();
+        let config = config(&[
+            &keys.config(),
+            &first.audience("first"),
+            &second.audience("second"),
+        ]);
+        let result = success(&config, &["email"]);
+
+        let token = issue(&config, Some("first"), None, &result, false).unwrap();
+        assert!(open(&token, &first).is_some());
+        assert!(open(&token, &second).is_none());
+        assert!(open(&token, &keys).is_none());
+        let token = issue(&config, Some("second"), None, &result, false).unwrap();
+        assert!(open(&token, &second).is_some());
+        assert!(open(&token, &first).is_none());
+
+        assert!(matches!(
+            issue(&config, Some("third"), None, &result, false),
+            Err(Error::Config(crate::config::Error::UnknownAudience(audience))) if audience == "third"
+        ));
+    }
+
+    #[test]
+    fn inspects_signed_and_encrypted_results() {
+        let keys = TestKeys::rsa();
+        let signed = config(&["encrypt_results = false", &keys.config()]);
+        let result = success(&signed, &["email"]);
+
+        let token = issue(&signed, None, None, &result, false).unwrap();
+        let inspected = inspect(&signed, &token).unwrap().unwrap();
+        assert_eq!(inspected.attributes, result.attributes);
+
+        let encrypted = config(&[&keys.config()]);
+        let token = issue(&encrypted, None, None, &result, false).unwrap();
+        let inspected = inspect(&encrypted, &token).unwrap().unwrap();
+        assert_eq!(inspected.attributes, result.attributes);
+    }
+
+    #[test]
+    fn sets_the_configured_timing_claims() {
+        let keys = TestKeys::rsa();
+        let config = config(&[
+            "encrypt_results = false\ntoken_lifetime_seconds = 600\ntoken_not_before_skew_seconds = 30",
+            &keys.config(),
+        ]);
+        let result = success(&config, &["email"]);
+
+        let payload = verify(
+            &issue(&config, None, None, &result, false).unwrap(),
+            &keys.signing,
+        );
+        let issued_at = payload.issued_at().unwrap();
+        let expires_at = payload.expires_at().unwrap();
+        assert_eq!(
+            expires_at.duration_since(issued_at).unwrap(),
+            Duration::from_secs(600)
+        );
+        assert_eq!(
+            issued_at
+                .duration_since(payload.not_before().unwrap())
+                .unwrap(),
+            Duration::from_secs(30)
+        );
+        assert!(expires_at > SystemTime::now());
+
+        let payload = verify(
+            &issue(&config, None, None, &result, true).unwrap(),
+            &keys.signing,
+        );
+        assert!(payload.expires_at().unwrap() < SystemTime::now());
+    }
+
+    #[test]
+    fn signs_with_the_active_rotated_key() {
+        let keys = TestKeys::rsa();
+        let first = KeyPair::rsa();
+        let second = KeyPair::rsa();
+        let config = config(&[
+            "encrypt_results = false\nactive_signing_kid = \"first\"",
+            &keys.config(),
+            &first.table("signing_keys.first", true),
+            &second.table("signing_keys.second", true),
+        ]);
+        let result = success(&config, &["email"]);
+
+        let token = issue(&config, None, None, &result, false).unwrap();
+        assert_eq!(header(&token)["kid"], "first");
+        verify(&token, &first);
+
+        let rotation = config.key_rotation().unwrap();
+        assert_eq!(rotation.activate("second").unwrap(), "first");
+        let token = issue(&config, None, None, &result, false).unwrap();
+        assert_eq!(header(&token)["kid"], "second");
+        verify(&token, &second);
+        assert!(rotation.activate("third").is_err());
+    }
+
+    #[test]
+    fn serializes_tokens_as_flattened_json() {
+        let keys = TestKeys::rsa();
+        let config = config(&[&keys.config()]);
+        let result = success(&config, &["email"]);
+        let token = issue(&config, None, None, &result, false).unwrap();
+        let parts: Vec<&str> = token.split('.').collect();
+
+        assert_eq!(serialize(&token, TokenSerialization::Compact), token);
+        let serialized: Value =
+            serde_json::from_str(&serialize(&token, TokenSerialization::Json)).unwrap();
+        assert_eq!(
+            serialized,
+            serde_json::json!({
+                "protected": parts[0],
+                "encrypted_key": parts[1],
+                "iv": parts[2],
+                "ciphertext": parts[3],
+                "tag": parts[4],
+            })
+        );
+        let placeholder = "auth-test-token:{}";
+        assert_eq!(
+            serialize(placeholder, TokenSerialization::Json),
+            placeholder
+        );
+    }
+
+    #[test]
+    fn sets_the_configured_or_requested_issuer() {
+        let keys = TestKeys::rsa();
+        let config = config(&[
+            "encrypt_results = false\nissuer = \"https://auth-test.example.com\"",
+            &keys.config(),
+        ]);
+        let result = success(&config, &["email"]);
+
+        let token = issue(&config, None, None, &result, false).unwrap();
+        let payload = verify(&token, &keys.signing);
+        assert_eq!(payload.issuer(), Some("https://auth-test.example.com"));
+        let issuer = Some("https://other.example.com");
+        let token = issue(&config, None, issuer, &result, false).unwrap();
+        assert_eq!(verify(&token, &keys.signing).issuer(), issuer);
+    }
+
+    #[test]
+    fn shapes_attributes_as_an_array() {
+        let keys = TestKeys::rsa();
+        let keys_config = keys.config();
+        let layers: [&str; 3] = [
+            "attribute_result_shape = \"array\"",
+            &keys_config,
+            r#"
+            [attributes]
+            name = "Test User"
+            "#,
+        ];
+        let result = |config: &Config| success(config, &["name", "email"]);
+
+        let signed_layers: Vec<&str> = std::iter::once("encrypt_results = false")
+            .chain(layers)
+            .collect();
+        let signed = config(&signed_layers);
+        let token = issue(&signed, None, None, &result(&signed), false).unwrap();
+        assert_eq!(
+            verify(&token, &keys.signing).claim("attributes"),
+            Some(&serde_json::json!([
+                { "name": "email", "value": "user@example.com" },
+                { "name": "name", "value": "Test User" },
+            ]))
+        );
+        let inspected = inspect(&signed, &token).unwrap().unwrap();
+        assert_eq!(inspected.attributes, result(&signed).attributes);
+
+        let encrypted = config(&layers);
+        let token = issue(&encrypted, None, None, &result(&encrypted), false).unwrap();
+        assert_eq!(token.split('.').count(), 5);
+        let inspected = inspect(&encrypted, &token).unwrap().unwrap();
+        assert_eq!(inspected.attributes, result(&encrypted).attributes);
+    }
+}